@@ -1,11 +1,113 @@
 use chrono::prelude::*;
 use chrono::{Duration, Local};
 
+use crate::error::{Error, Result};
+use crate::Session;
+
 pub fn yyyymmdd_today_plus_n(n: i64) -> String {
     let today: DateTime<Local> = Local::now() + Duration::days(n);
     today.format("%Y-%m-%d").to_string()
 }
 
+pub fn now_hhmm() -> String {
+    Local::now().format("%H:%M").to_string()
+}
+
+/// Turns "today", "+3d" or "-7d" into a date relative to today
+/// Any other string is returned unchanged
+pub fn resolve_date(date: &str) -> String {
+    let date = date.trim();
+    if date == "today" {
+        return yyyymmdd_today_plus_n(0);
+    }
+    if (date.starts_with('+') || date.starts_with('-')) && date.ends_with('d') {
+        if let Ok(n) = date[..date.len() - 1].parse::<i64>() {
+            return yyyymmdd_today_plus_n(n);
+        }
+    }
+    date.to_string()
+}
+
+/// Adds n days to a YYYY-MM-DD date
+pub fn date_plus_n(date: &str, n: i64) -> Result<String> {
+    let parsed = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| Error::Validation(format!("'{}' is not a YYYY-MM-DD date", date)))?;
+    Ok((parsed + Duration::days(n)).format("%Y-%m-%d").to_string())
+}
+
+/// Days between two YYYY-MM-DD dates, negative when to is before from
+pub fn days_between(from: &str, to: &str) -> Result<i64> {
+    let parse = |date: &str| {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| Error::Validation(format!("'{}' is not a YYYY-MM-DD date", date)))
+    };
+    Ok((parse(to)? - parse(from)?).num_days())
+}
+
+/// Interval of a repeat string like "+1d" or "+2w" in days
+pub fn repeat_days(repeat: &str) -> Option<i64> {
+    let body = repeat.trim().strip_prefix('+')?;
+    let (count, unit) = body.split_at(body.len().checked_sub(1)?);
+    let count = count.parse::<i64>().ok().filter(|n| *n > 0)?;
+    match unit {
+        "d" => Some(count),
+        "w" => Some(count * 7),
+        _ => None,
+    }
+}
+
+/// True when a routine that is next due on `next` is due again on `date`
+/// Repeat strings that can't be read only match their next date
+pub fn routine_falls_on(next: &str, repeat: &str, date: &str) -> bool {
+    match (days_between(next, date), repeat_days(repeat)) {
+        (Ok(0), _) => true,
+        (Ok(days), Some(interval)) => days > 0 && days % interval == 0,
+        _ => false,
+    }
+}
+
+/// First date on or after `from` that a routine next due on `next` falls on
+/// Repeat strings that can't be read only fall on their next date
+pub fn first_routine_date(next: &str, repeat: &str, from: &str) -> Option<String> {
+    let days = days_between(next, from).ok()?;
+    if days <= 0 {
+        return Some(next.to_string());
+    }
+    let interval = repeat_days(repeat)?;
+    let steps = (days + interval - 1) / interval;
+    date_plus_n(next, steps * interval).ok()
+}
+
+/// ISO week of a date as "2020-W27" and the dates from its Monday to Sunday
+pub fn iso_week(date: &str) -> Result<(String, Vec<String>)> {
+    let parsed = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| Error::Validation(format!("'{}' is not a YYYY-MM-DD date", date)))?;
+    let week = parsed.iso_week();
+    let monday = parsed - Duration::days(parsed.weekday().num_days_from_monday().into());
+    let dates = (0..7)
+        .map(|n| (monday + Duration::days(n)).format("%Y-%m-%d").to_string())
+        .collect();
+
+    Ok((format!("{}-W{:02}", week.year(), week.week()), dates))
+}
+
+/// Day name like "Monday" for a YYYY-MM-DD date
+pub fn weekday_name(date: &str) -> String {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|d| d.format("%A").to_string())
+        .unwrap_or_default()
+}
+
+/// Turns a YYYYMMDD file stem into a YYYY-MM-DD date
+pub fn date_from_file_stem(stem: &str) -> Option<String> {
+    if stem.len() != 8 {
+        return None;
+    }
+    NaiveDate::parse_from_str(stem, "%Y%m%d")
+        .ok()
+        .map(|date| date.format("%Y-%m-%d").to_string())
+}
+
 pub fn days_range(start: i32, end: i32) -> Vec<String> {
     let today: DateTime<Local> = Local::now();
     let mut vec = Vec::new();
@@ -16,10 +118,134 @@ pub fn days_range(start: i32, end: i32) -> Vec<String> {
     vec
 }
 
-pub fn get_duration(log_start: &str, log_end: &str) -> String {
-    let parse_from_str = NaiveTime::parse_from_str;
+/// Minutes from start to end, sessions ending before they start are taken to cross midnight
+pub fn get_minutes(log_start: &str, log_end: &str) -> Result<i64> {
+    let mut duration = parse_time(log_end)? - parse_time(log_start)?;
+    if duration < Duration::zero() {
+        duration = duration + Duration::days(1);
+    }
+    Ok(Duration::num_minutes(&duration))
+}
+
+/// Accepts H:MM, HH:MM, HH:MM:SS and 12-hour times like 9:30pm or 9 PM
+pub fn parse_time(time: &str) -> Result<NaiveTime> {
+    let invalid = || Error::Validation(format!("'{}' is not a valid time", time));
+    let lower = time.trim().to_lowercase().replace(' ', "");
+
+    let (body, pm) = if let Some(body) = lower.strip_suffix("am") {
+        (body, Some(false))
+    } else if let Some(body) = lower.strip_suffix("pm") {
+        (body, Some(true))
+    } else {
+        (lower.as_str(), None)
+    };
+
+    let parsed = NaiveTime::parse_from_str(body, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(body, "%H:%M"))
+        .ok()
+        .or_else(|| match (pm, body.parse::<u32>()) {
+            (Some(_), Ok(hour)) => NaiveTime::from_hms_opt(hour, 0, 0),
+            _ => None,
+        })
+        .ok_or_else(invalid)?;
 
-    let duration_string =
-        parse_from_str(&log_end, "%H:%M").unwrap() - parse_from_str(&log_start, "%H:%M").unwrap();
-    Duration::num_minutes(&duration_string).to_string()
+    match pm {
+        None => Ok(parsed),
+        Some(pm) => {
+            if parsed.hour() == 0 || parsed.hour() > 12 {
+                return Err(invalid());
+            }
+            let hour = parsed.hour() % 12 + if pm { 12 } else { 0 };
+            parsed.with_hour(hour).ok_or_else(invalid)
+        }
+    }
+}
+
+/// Writes a time as HH:MM, or HH:MM:SS when it has seconds
+pub fn format_time(time: NaiveTime) -> String {
+    if time.second() == 0 {
+        time.format("%H:%M").to_string()
+    } else {
+        time.format("%H:%M:%S").to_string()
+    }
+}
+
+/// Total minutes of all sessions
+pub fn get_session_minutes(sessions: &[Session]) -> Result<i64> {
+    let mut total = 0;
+    for session in sessions {
+        total += get_minutes(&session.start, &session.end)?;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hhmm(time: &str) -> String {
+        format_time(parse_time(time).unwrap())
+    }
+
+    #[test]
+    fn twenty_four_hour_times() {
+        assert_eq!(hhmm("9:05"), "09:05");
+        assert_eq!(hhmm("09:05"), "09:05");
+        assert_eq!(hhmm("23:59:30"), "23:59:30");
+        assert_eq!(hhmm(" 00:00 "), "00:00");
+    }
+
+    #[test]
+    fn twelve_hour_times() {
+        assert_eq!(hhmm("12am"), "00:00");
+        assert_eq!(hhmm("12:30am"), "00:30");
+        assert_eq!(hhmm("12pm"), "12:00");
+        assert_eq!(hhmm("12:30 PM"), "12:30");
+        assert_eq!(hhmm("9 PM"), "21:00");
+        assert_eq!(hhmm("9:30pm"), "21:30");
+        assert_eq!(hhmm("1:15am"), "01:15");
+    }
+
+    #[test]
+    fn invalid_times_are_rejected() {
+        for time in &["13pm", "0pm", "0:30am", "24:00", "9", "9:60", "nine", ""] {
+            assert!(parse_time(time).is_err(), "{} was accepted", time);
+        }
+    }
+
+    #[test]
+    fn minutes_wrap_past_midnight() {
+        assert_eq!(get_minutes("09:00", "10:30").unwrap(), 90);
+        assert_eq!(get_minutes("23:30", "00:15").unwrap(), 45);
+        assert_eq!(get_minutes("10pm", "2am").unwrap(), 240);
+        assert_eq!(get_minutes("09:00", "09:00").unwrap(), 0);
+        assert!(get_minutes("09:00", "later").is_err());
+    }
+
+    #[test]
+    fn session_minutes_add_up() {
+        let sessions = vec![
+            Session {
+                start: "09:00".to_string(),
+                end: "09:45".to_string(),
+            },
+            Session {
+                start: "23:50".to_string(),
+                end: "00:20".to_string(),
+            },
+        ];
+
+        assert_eq!(get_session_minutes(&sessions).unwrap(), 75);
+    }
+
+    #[test]
+    fn first_routine_date_on_or_after_from() {
+        let first = |next, repeat| first_routine_date(next, repeat, "2020-07-01");
+
+        assert_eq!(first("2020-07-03", "+1w"), Some("2020-07-03".to_string()));
+        assert_eq!(first("2020-06-03", "+1w"), Some("2020-07-01".to_string()));
+        assert_eq!(first("2020-06-05", "+1w"), Some("2020-07-03".to_string()));
+        assert_eq!(first("2020-06-05", "+1m"), None);
+        assert_eq!(first("", "+1d"), None);
+    }
 }
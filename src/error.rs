@@ -0,0 +1,61 @@
+use std::fmt;
+use std::io;
+
+/// Errors reported to the user instead of aborting
+#[derive(Debug)]
+pub enum Error {
+    /// Config file could not be read or parsed
+    Config(String),
+    /// Reading or writing a file failed
+    Io(io::Error),
+    /// A line in a markdown file could not be understood
+    Parse { line: usize, message: String },
+    /// A SQLite query failed
+    Database(rusqlite::Error),
+    /// User input or stored data is not valid
+    Validation(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Line number for parse errors, 0 for everything else
+    pub fn line(&self) -> usize {
+        match self {
+            Error::Parse { line, .. } => *line,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(message) => write!(f, "Config error: {}", message),
+            Error::Io(err) => write!(f, "File error: {}", err),
+            Error::Parse { line, message } => write!(f, "Line {}: {}", line, message),
+            Error::Database(err) => write!(f, "Database error: {}", err),
+            Error::Validation(message) => write!(f, "Invalid input: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Error {
+        Error::Database(err)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Error {
+        Error::Config(err.to_string())
+    }
+}
@@ -0,0 +1,611 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use std::fmt;
+
+use crate::datetime;
+use crate::Task;
+
+/// Project given to tasks imported from a calendar
+pub const CALENDAR_PROJECT: &str = "Calendar";
+
+/// One occurrence of a timed calendar event
+#[derive(Debug, Clone)]
+pub struct CalendarEvent {
+    /// The event's UID, for an occurrence of a recurring event followed by "/" and
+    /// the date it was first scheduled on (its RECURRENCE-ID), so a moved occurrence keeps its key
+    pub key: String,
+    pub date: String,
+    pub start: String,
+    pub minutes: i32,
+    pub summary: String,
+    pub cancelled: bool,
+}
+
+/// What a calendar export wrote and left out
+#[derive(Debug, Default)]
+pub struct ExportSummary {
+    pub exported: usize,
+    /// Tasks without a date or a readable start time
+    pub skipped: usize,
+    /// Routines exported as a single event because their repeat has no RRULE equivalent
+    pub not_repeated: usize,
+}
+
+impl fmt::Display for ExportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} events exported, {} tasks without a date or start time left out",
+            self.exported, self.skipped
+        )?;
+        if self.not_repeated > 0 {
+            write!(
+                f,
+                ", {} routines exported once because their repeat isn't like +1d or +2w",
+                self.not_repeated
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// iCalendar file with one event per task that has a date and a readable start time
+/// Routines repeat with an RRULE from their repeat string, starting on their next date,
+/// and stop after `until` when it is given, so a range export doesn't repeat forever
+pub fn tasks_to_ics(tasks: &[Task], until: Option<&str>) -> (String, ExportSummary) {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//tasketext//tasketext//EN".to_string(),
+    ];
+    let mut summary = ExportSummary::default();
+
+    for task in tasks {
+        let start = match (task.next.is_empty(), datetime::parse_time(&task.start)) {
+            (false, Ok(time)) => format!("{}T{}", task.next.replace("-", ""), time.format("%H%M%S")),
+            _ => {
+                summary.skipped += 1;
+                continue;
+            }
+        };
+
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:task-{}@tasketext", task.id));
+        lines.push(format!("DTSTAMP:{}", stamp));
+        lines.push(format!("DTSTART:{}", start));
+        lines.push(format!("DURATION:PT{}M", task.estimate.max(0)));
+        lines.push(format!("SUMMARY:{}", escape_text(&task.name)));
+        if !task.notes.is_empty() {
+            lines.push(format!("DESCRIPTION:{}", escape_text(&task.notes)));
+        }
+        if !task.project.is_empty() {
+            lines.push(format!("CATEGORIES:{}", escape_text(&task.project)));
+        }
+        if !task.repeat.is_empty() {
+            match repeat_to_rrule(&task.repeat) {
+                // DTSTART is floating local time, so UNTIL is too
+                Some(rule) => match until {
+                    Some(until) => {
+                        lines.push(format!("RRULE:{};UNTIL={}T235959", rule, until.replace("-", "")))
+                    }
+                    None => lines.push(format!("RRULE:{}", rule)),
+                },
+                None => summary.not_repeated += 1,
+            }
+        }
+        lines.push("END:VEVENT".to_string());
+        summary.exported += 1;
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    let calendar = lines.iter().map(|l| fold_line(l)).collect::<Vec<String>>().join("\r\n");
+    (calendar + "\r\n", summary)
+}
+
+/// "+1d" becomes FREQ=DAILY, "+2w" becomes FREQ=WEEKLY;INTERVAL=2
+fn repeat_to_rrule(repeat: &str) -> Option<String> {
+    let days = datetime::repeat_days(repeat)?;
+    let (freq, interval) = if days % 7 == 0 {
+        ("WEEKLY", days / 7)
+    } else {
+        ("DAILY", days)
+    };
+    if interval == 1 {
+        Some(format!("FREQ={}", freq))
+    } else {
+        Some(format!("FREQ={};INTERVAL={}", freq, interval))
+    }
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Lines longer than 75 bytes continue on the next line after a space
+fn fold_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded
+}
+
+/// Reads timed events from iCalendar text that fall on dates from `from` to `to`
+/// Recurring events are expanded for FREQ=DAILY and FREQ=WEEKLY without the dates in EXDATE,
+/// other rules only keep their first occurrence, and events with a RECURRENCE-ID replace the
+/// occurrence they move. All-day events are skipped, cancelled events are returned as cancelled
+/// Returns the events and a warning for everything that was left out or simplified
+pub fn parse_ics(text: &str, from: &str, to: &str) -> (Vec<CalendarEvent>, Vec<String>) {
+    let mut vevents: Vec<Vec<Property>> = Vec::new();
+    let mut properties: Option<Vec<Property>> = None;
+    let mut in_alarm = false;
+
+    for line in unfold_lines(text) {
+        match line.as_str() {
+            "BEGIN:VEVENT" => properties = Some(Vec::new()),
+            "BEGIN:VALARM" => in_alarm = true,
+            "END:VALARM" => in_alarm = false,
+            "END:VEVENT" => vevents.extend(properties.take()),
+            _ => {
+                if let (Some(props), false) = (properties.as_mut(), in_alarm) {
+                    if let Some((head, value)) = line.split_once(':') {
+                        let (name, params) = head.split_once(';').unwrap_or((head, ""));
+                        props.push((name.to_uppercase(), params.to_string(), value.to_string()));
+                    }
+                }
+            }
+        }
+    }
+
+    // Occurrences moved or cancelled by a RECURRENCE-ID event are left out of their series,
+    // also when they were moved out of the range
+    let (overrides, series): (Vec<_>, Vec<_>) = vevents
+        .into_iter()
+        .partition(|props| props.iter().any(|(name, _, _)| name == "RECURRENCE-ID"));
+    let mut events = Vec::new();
+    let mut warnings = Vec::new();
+    for props in &overrides {
+        read_event(props, "0000-01-01", "9999-12-31", &[], &mut events, &mut warnings);
+    }
+    let overridden: Vec<String> = events.iter().map(|e| e.key.clone()).collect();
+    events.retain(|e| e.date.as_str() >= from && e.date.as_str() <= to);
+    let mut series_events = Vec::new();
+    for props in &series {
+        read_event(props, from, to, &overridden, &mut series_events, &mut warnings);
+    }
+    events.extend(series_events);
+
+    (events, warnings)
+}
+
+/// Name in upper case, parameters and value of a content line
+type Property = (String, String, String);
+
+fn read_event(
+    props: &[Property],
+    from: &str,
+    to: &str,
+    overridden: &[String],
+    events: &mut Vec<CalendarEvent>,
+    warnings: &mut Vec<String>,
+) {
+    let get = |name: &str| props.iter().find(|(n, _, _)| n == name);
+    let summary = get("SUMMARY").map_or("(no title)".to_string(), |(_, _, v)| unescape_text(v));
+    let cancelled = get("STATUS").is_some_and(|(_, _, v)| v.eq_ignore_ascii_case("CANCELLED"));
+
+    let start = match get("DTSTART") {
+        Some((_, params, _)) if params.to_uppercase().split(';').any(|p| p == "VALUE=DATE") => None,
+        Some((_, _, value)) => parse_date_time(value),
+        None => None,
+    };
+    let start = match start {
+        Some(start) => start,
+        None => {
+            warnings.push(format!("{}: skipped, not a timed event", summary));
+            return;
+        }
+    };
+    let zone = get("DTSTART").and_then(|(_, params, _)| {
+        params.split(';').find_map(|p| match p.split_once('=') {
+            Some((name, zone)) if name.eq_ignore_ascii_case("TZID") => Some(zone),
+            _ => None,
+        })
+    });
+    if let Some(zone) = zone {
+        warnings.push(format!(
+            "{}: time zone {} isn't converted, times are read as local time",
+            summary, zone
+        ));
+    }
+
+    let minutes = match (get("DTEND").and_then(|(_, _, v)| parse_date_time(v)), get("DURATION")) {
+        (Some(end), _) => (end - start).num_minutes(),
+        (None, Some((_, _, duration))) => parse_duration(duration),
+        (None, None) => 0,
+    };
+    let uid = get("UID").map_or(format!("{}-{}", summary, start), |(_, _, v)| v.clone());
+
+    // (date the occurrence was scheduled on, date it happens on)
+    let mut dates = vec![(start.date(), start.date())];
+    if let Some((_, _, recurrence)) = get("RECURRENCE-ID") {
+        let original = recurrence.get(..8).and_then(|d| NaiveDate::parse_from_str(d, "%Y%m%d").ok());
+        match original {
+            Some(original) => dates = vec![(original, start.date())],
+            None => {
+                warnings.push(format!("{}: skipped, RECURRENCE-ID {} can't be read", summary, recurrence));
+                return;
+            }
+        }
+    } else if let Some((_, _, rule)) = get("RRULE") {
+        match expand_rule(rule, start.date(), to) {
+            Some(expanded) => dates = expanded.into_iter().map(|d| (d, d)).collect(),
+            None => warnings.push(format!(
+                "{}: only the first occurrence of {} is imported",
+                summary, rule
+            )),
+        }
+    }
+    let recurring = get("RRULE").is_some() || get("RECURRENCE-ID").is_some();
+
+    let excluded: Vec<NaiveDate> = props
+        .iter()
+        .filter(|(name, _, _)| name == "EXDATE")
+        .flat_map(|(_, _, value)| value.split(','))
+        .filter_map(|d| NaiveDate::parse_from_str(d.get(..8)?, "%Y%m%d").ok())
+        .collect();
+
+    for (original, date) in dates {
+        if excluded.contains(&original) {
+            continue;
+        }
+        let key = if recurring {
+            format!("{}/{}", uid, original.format("%Y-%m-%d"))
+        } else {
+            uid.clone()
+        };
+        let date = date.format("%Y-%m-%d").to_string();
+        if date.as_str() < from || date.as_str() > to || overridden.contains(&key) {
+            continue;
+        }
+        events.push(CalendarEvent {
+            key,
+            date,
+            start: start.format("%H:%M").to_string(),
+            minutes: minutes.max(0) as i32,
+            summary: summary.clone(),
+            cancelled,
+        });
+    }
+}
+
+/// Dates of a FREQ=DAILY or FREQ=WEEKLY rule up to the given date, None for other rules
+fn expand_rule(rule: &str, first: NaiveDate, to: &str) -> Option<Vec<NaiveDate>> {
+    let mut freq = "";
+    let mut interval = 1;
+    let mut count: Option<usize> = None;
+    let mut until: Option<NaiveDate> = None;
+    for part in rule.split(';') {
+        match part.split_once('=') {
+            Some(("FREQ", value)) => freq = value,
+            Some(("INTERVAL", value)) => interval = value.parse().ok()?,
+            Some(("COUNT", value)) => count = Some(value.parse().ok()?),
+            Some(("UNTIL", value)) => {
+                until = Some(NaiveDate::parse_from_str(value.get(..8)?, "%Y%m%d").ok()?)
+            }
+            // A single weekday is the weekday of the first occurrence
+            Some(("BYDAY", value)) if freq == "WEEKLY" && !value.contains(',') => (),
+            Some(("WKST", _)) => (),
+            _ => return None,
+        }
+    }
+    let step = match freq {
+        "DAILY" => interval,
+        "WEEKLY" => interval * 7,
+        _ => return None,
+    };
+    if step <= 0 {
+        return None;
+    }
+
+    let mut last = NaiveDate::parse_from_str(to, "%Y-%m-%d").ok()?;
+    if let Some(until) = until {
+        last = last.min(until);
+    }
+    let mut dates = Vec::new();
+    let mut date = first;
+    while date <= last {
+        if let Some(count) = count {
+            if dates.len() >= count {
+                break;
+            }
+        }
+        dates.push(date);
+        date += Duration::days(step);
+    }
+    Some(dates)
+}
+
+/// Times ending in Z are UTC and turned into local time, others are taken as local
+fn parse_date_time(value: &str) -> Option<NaiveDateTime> {
+    match value.strip_suffix('Z') {
+        Some(utc) => NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")
+            .ok()
+            .map(|t| Utc.from_utc_datetime(&t).with_timezone(&Local).naive_local()),
+        None => NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok(),
+    }
+}
+
+/// Minutes of a duration like PT1H30M or P1D
+fn parse_duration(value: &str) -> i64 {
+    let mut minutes = 0;
+    let mut number = String::new();
+    for c in value.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'W' | 'D' | 'H' | 'M' | 'S' => {
+                let n: i64 = number.parse().unwrap_or(0);
+                minutes += match c {
+                    'W' => n * 7 * 24 * 60,
+                    'D' => n * 24 * 60,
+                    'H' => n * 60,
+                    'M' => n,
+                    _ => 0,
+                };
+                number.clear();
+            }
+            _ => (),
+        }
+    }
+    minutes
+}
+
+/// Joins lines continued with a leading space or tab
+fn unfold_lines(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.trim_end_matches('\r').to_string()),
+        }
+    }
+    lines
+}
+
+fn unescape_text(text: &str) -> String {
+    text.replace("\\n", " ")
+        .replace("\\N", " ")
+        .replace("\\,", ",")
+        .replace("\\;", ";")
+        .replace("\\\\", "\\")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn standup(id: i32, start: &str) -> Task {
+        Task::named("Standup, daily").with_id(id).at(start, 15)
+    }
+
+    fn lines_starting(calendar: &str, prefix: &str) -> Vec<String> {
+        calendar
+            .split("\r\n")
+            .filter(|l| l.starts_with(prefix))
+            .map(|l| l.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn export_writes_timed_tasks_and_skips_the_rest() {
+        let tasks = vec![
+            standup(1, "09:00"),
+            standup(2, ""),
+            standup(3, "09:00").dated(""),
+        ];
+        let (calendar, summary) = tasks_to_ics(&tasks, None);
+
+        assert_eq!(summary.exported, 1);
+        assert_eq!(summary.skipped, 2);
+        assert_eq!(
+            lines_starting(&calendar, "DTSTART"),
+            vec!["DTSTART:20200701T090000"]
+        );
+        assert_eq!(
+            lines_starting(&calendar, "SUMMARY"),
+            vec!["SUMMARY:Standup\\, daily"]
+        );
+        assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+    }
+
+    #[test]
+    fn range_export_bounds_routines() {
+        let tasks = vec![
+            standup(1, "09:00").repeating("+1d"),
+            standup(2, "09:00").repeating("+2w"),
+        ];
+
+        let (calendar, _) = tasks_to_ics(&tasks, Some("2020-07-07"));
+        assert_eq!(
+            lines_starting(&calendar, "RRULE"),
+            vec![
+                "RRULE:FREQ=DAILY;UNTIL=20200707T235959",
+                "RRULE:FREQ=WEEKLY;INTERVAL=2;UNTIL=20200707T235959"
+            ]
+        );
+
+        let (calendar, _) = tasks_to_ics(&tasks, None);
+        assert_eq!(
+            lines_starting(&calendar, "RRULE"),
+            vec!["RRULE:FREQ=DAILY", "RRULE:FREQ=WEEKLY;INTERVAL=2"]
+        );
+    }
+
+    #[test]
+    fn routine_without_rrule_equivalent_is_counted() {
+        let (calendar, summary) = tasks_to_ics(&[standup(1, "09:00").repeating("+1m")], None);
+
+        assert_eq!(summary.exported, 1);
+        assert_eq!(summary.not_repeated, 1);
+        assert!(lines_starting(&calendar, "RRULE").is_empty());
+    }
+
+    #[test]
+    fn long_lines_are_folded() {
+        let folded = fold_line(&"x".repeat(100));
+
+        assert_eq!(folded, format!("{}\r\n {}", "x".repeat(75), "x".repeat(25)));
+        assert_eq!(unfold_lines(&folded), vec!["x".repeat(100)]);
+    }
+
+    fn calendar(events: &[&str]) -> String {
+        let mut text = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\n".to_string();
+        for event in events {
+            text.push_str("BEGIN:VEVENT\r\n");
+            text.push_str(&event.replace('\n', "\r\n"));
+            text.push_str("\r\nEND:VEVENT\r\n");
+        }
+        text + "END:VCALENDAR\r\n"
+    }
+
+    fn occurrences(events: &[CalendarEvent]) -> Vec<(&str, &str, &str)> {
+        events
+            .iter()
+            .map(|e| (e.key.as_str(), e.date.as_str(), e.start.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn folded_lines_are_joined() {
+        let text = calendar(&["UID:a\nDTSTART:20200701T090000\nDURATION:PT1H30M\nSUMMARY:Quarterly\n  planning\\, part 1"]);
+        let (events, warnings) = parse_ics(&text, "2020-07-01", "2020-07-01");
+
+        assert!(warnings.is_empty());
+        assert_eq!(events[0].summary, "Quarterly planning, part 1");
+        assert_eq!(events[0].minutes, 90);
+    }
+
+    #[test]
+    fn all_day_events_are_skipped_and_timed_ones_kept() {
+        let text = calendar(&[
+            "UID:a\nDTSTART;VALUE=DATE:20200701\nSUMMARY:Holiday",
+            "UID:b\nDTSTART:20200701T140000\nDTEND:20200701T144500\nSUMMARY:Call",
+            "UID:c\nDTSTART:20200705T140000\nSUMMARY:Later",
+        ]);
+        let (events, warnings) = parse_ics(&text, "2020-07-01", "2020-07-02");
+
+        assert_eq!(warnings, vec!["Holiday: skipped, not a timed event"]);
+        assert_eq!(occurrences(&events), vec![("b", "2020-07-01", "14:00")]);
+        assert_eq!(events[0].minutes, 45);
+    }
+
+    fn date(text: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn rules_with_count_and_until_stop_early() {
+        let first = date("2020-07-01");
+
+        let dates = expand_rule("FREQ=DAILY;COUNT=3", first, "2020-07-31").unwrap();
+        assert_eq!(dates.last(), Some(&date("2020-07-03")));
+        assert_eq!(dates.len(), 3);
+
+        let dates = expand_rule(
+            "FREQ=WEEKLY;INTERVAL=2;UNTIL=20200729T090000Z",
+            first,
+            "2020-12-31",
+        )
+        .unwrap();
+        assert_eq!(dates, vec![first, date("2020-07-15"), date("2020-07-29")]);
+
+        let dates = expand_rule("FREQ=DAILY", first, "2020-07-02").unwrap();
+        assert_eq!(dates.len(), 2);
+
+        assert!(expand_rule("FREQ=MONTHLY", first, "2020-07-31").is_none());
+        assert!(expand_rule("FREQ=WEEKLY;BYDAY=MO,WE", first, "2020-07-31").is_none());
+    }
+
+    #[test]
+    fn recurring_occurrences_are_keyed_by_their_date_and_exdates_left_out() {
+        let text = calendar(&["UID:standup\nDTSTART:20200701T090000\nDURATION:PT15M\nSUMMARY:Standup\nRRULE:FREQ=DAILY;COUNT=4\nEXDATE:20200702T090000,20200703T090000"]);
+        let (events, _) = parse_ics(&text, "2020-07-01", "2020-07-31");
+
+        assert_eq!(
+            occurrences(&events),
+            vec![
+                ("standup/2020-07-01", "2020-07-01", "09:00"),
+                ("standup/2020-07-04", "2020-07-04", "09:00")
+            ]
+        );
+    }
+
+    #[test]
+    fn moved_occurrence_keeps_its_key() {
+        let text = calendar(&[
+            "UID:sync\nDTSTART:20200701T100000\nSUMMARY:Sync\nRRULE:FREQ=WEEKLY;COUNT=2",
+            "UID:sync\nRECURRENCE-ID:20200708T100000\nDTSTART:20200709T150000\nSUMMARY:Sync (moved)",
+        ]);
+        let (events, _) = parse_ics(&text, "2020-07-01", "2020-07-31");
+
+        assert_eq!(
+            occurrences(&events),
+            vec![
+                ("sync/2020-07-08", "2020-07-09", "15:00"),
+                ("sync/2020-07-01", "2020-07-01", "10:00")
+            ]
+        );
+
+        // Moved past the end of the range, the original occurrence is not imported either
+        let text = calendar(&[
+            "UID:sync\nDTSTART:20200701T100000\nSUMMARY:Sync\nRRULE:FREQ=WEEKLY;COUNT=2",
+            "UID:sync\nRECURRENCE-ID:20200708T100000\nDTSTART:20200720T100000\nSUMMARY:Sync (moved)",
+        ]);
+        let (events, _) = parse_ics(&text, "2020-07-01", "2020-07-10");
+
+        assert_eq!(
+            occurrences(&events),
+            vec![("sync/2020-07-01", "2020-07-01", "10:00")]
+        );
+    }
+
+    #[test]
+    fn cancelled_events_are_marked() {
+        let text = calendar(&[
+            "UID:a\nDTSTART:20200701T090000\nSUMMARY:Off\nSTATUS:CANCELLED",
+            "UID:b\nDTSTART:20200701T100000\nSUMMARY:Series\nRRULE:FREQ=DAILY;COUNT=2",
+            "UID:b\nRECURRENCE-ID:20200702T100000\nDTSTART:20200702T100000\nSUMMARY:Series\nSTATUS:CANCELLED",
+        ]);
+        let (events, _) = parse_ics(&text, "2020-07-01", "2020-07-31");
+
+        let cancelled: Vec<(&str, bool)> = events
+            .iter()
+            .map(|e| (e.key.as_str(), e.cancelled))
+            .collect();
+        assert_eq!(
+            cancelled,
+            vec![("b/2020-07-02", true), ("a", true), ("b/2020-07-01", false)]
+        );
+    }
+
+    #[test]
+    fn zoned_start_is_read_as_local_with_a_warning() {
+        let text = calendar(&["UID:a\nDTSTART;TZID=Europe/Berlin:20200701T090000\nSUMMARY:Call"]);
+        let (events, warnings) = parse_ics(&text, "2020-07-01", "2020-07-01");
+
+        assert_eq!(events[0].start, "09:00");
+        assert_eq!(
+            warnings,
+            vec!["Call: time zone Europe/Berlin isn't converted, times are read as local time"]
+        );
+    }
+}
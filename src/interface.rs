@@ -1,12 +1,15 @@
-use crate::sql;
+use crate::parser::{self, LineTemplate, LogEntry};
+use crate::query::SavedFilter;
+use crate::schedule::{self, Schedule};
+use crate::sql::{self, ImportMode, PlanLayout};
 use crate::Task;
 use comfy_table::presets::ASCII_MARKDOWN;
 use dialoguer::Input;
 use dialoguer::{theme::ColorfulTheme, Select, Confirm};
-use rusqlite::{Connection, Result};
+use rusqlite::Connection;
 use std::fs::File;
 use std::fs::OpenOptions;
-use std::io::{Error, Write};
+use std::io::Write;
 use term_table::{
     row::Row,
     table_cell::{Alignment, TableCell},
@@ -14,15 +17,23 @@ use term_table::{
 use term_table::{Table, TableStyle};
 
 use crate::datetime;
+use crate::error::{Error, Result};
+use crate::ics;
 use crate::Log;
 use crate::Note;
 use std::path::Path;
 
-pub fn main_menu(conn: &Connection, main_dir: String) -> Result<()> {
+pub fn main_menu(
+    conn: &Connection,
+    main_dir: String,
+    template: &LineTemplate,
+    schedule: Option<&Schedule>,
+    capacity: Option<i32>,
+) -> Result<()> {
     let logged = sql::check_log_for_date(conn, &datetime::yyyymmdd_today_plus_n(-1))?;
     if !logged {
-        if Confirm::new().with_prompt("Add yesterday's Markdown log to database?").interact().unwrap() {
-            yesterday_log_to_database(conn, &main_dir)?;
+        if Confirm::new().with_prompt("Add yesterday's Markdown log to database?").interact()? {
+            yesterday_log_to_database(conn, &main_dir, template)?;
         } else {
             println!("Run again after reviewing file");
         }
@@ -34,13 +45,22 @@ pub fn main_menu(conn: &Connection, main_dir: String) -> Result<()> {
         println!("Updated Routine Dates")
     }
 
+    rollover_overdue_tasks(conn)?;
+
     let selected = &[
         "Add a Task to Today's Plan",
         "Add a Task",
         "View Tasks",
         "Generate Plan",
+        "Generate Weekly Plan",
         "Markdown Log to Database",
         "Generate Daily Report",
+        "Estimate Accuracy Report",
+        "List Conflicts",
+        "Export Calendar",
+        "Import Calendar",
+        "Lint Markdown Log",
+        "Backfill Markdown Logs",
         "quit",
     ];
 
@@ -51,13 +71,20 @@ pub fn main_menu(conn: &Connection, main_dir: String) -> Result<()> {
         .interact();
 
     match selection {
-        Ok(0) => add_task_today(main_dir)?,
-        Ok(1) => call_add_task(&conn)?,
-        Ok(2) => view_tasks_menu(&conn)?,
-        Ok(3) => call_generate_daily_plan(&conn, main_dir)?,
-        Ok(4) => markdown_log_to_database(&conn, main_dir)?,
-        Ok(5) => generate_daily_report(&conn, main_dir)?,
-        Ok(6) => (),
+        Ok(0) => add_task_today(main_dir, template)?,
+        Ok(1) => call_add_task(conn, capacity)?,
+        Ok(2) => view_tasks_menu(conn)?,
+        Ok(3) => call_generate_daily_plan(conn, main_dir, template, schedule, capacity)?,
+        Ok(4) => call_generate_week_plan(conn, &main_dir, template)?,
+        Ok(5) => markdown_log_to_database(conn, main_dir, template)?,
+        Ok(6) => generate_daily_report(conn, main_dir)?,
+        Ok(7) => estimate_accuracy_report_menu(conn, &main_dir)?,
+        Ok(8) => list_conflicts_menu(conn)?,
+        Ok(9) => export_calendar_menu(conn, &main_dir)?,
+        Ok(10) => import_calendar_menu(conn)?,
+        Ok(11) => lint_markdown_log_menu(&main_dir, template)?,
+        Ok(12) => backfill_markdown_logs_menu(conn, &main_dir, template)?,
+        Ok(13) => (),
         Ok(_) => println!("Something went wrong"),
         Err(_err) => println!("Error"),
     }
@@ -67,7 +94,7 @@ pub fn main_menu(conn: &Connection, main_dir: String) -> Result<()> {
 
 /// Ask user for input and return entered String
 /// Displays given string
-fn user_input(displayed_text: &str) -> String {
+fn user_input(displayed_text: &str) -> Result<String> {
     let value: String = Input::new()
         .with_prompt(displayed_text)
         .validate_with(|input: &str| -> Result<(), &str> {
@@ -77,68 +104,83 @@ fn user_input(displayed_text: &str) -> String {
                 Err("This is not a valid input")
             }
         })
-        .interact()
-        .unwrap();
-    value
+        .interact()?;
+    Ok(value)
 }
 
-fn user_input_allow_empty(displayed_text: &str) -> String {
+fn user_input_allow_empty(displayed_text: &str) -> Result<String> {
     let value: String = Input::new()
         .allow_empty(true)
         .with_prompt(displayed_text)
-        .interact()
-        .unwrap();
-    value
+        .interact()?;
+    Ok(value)
 }
 
-fn user_input_repeat_string() -> String {
+fn user_input_repeat_string() -> Result<String> {
     let repeat: String = Input::new()
         .allow_empty(true)
         .with_prompt("Repeat")
         .validate_with(|input: &str| -> Result<(), &str> {
-            if input.contains('+') {
-                Ok(())
-            } else if input.is_empty() {
+            if input.contains('+') || input.is_empty() {
                 Ok(())
             } else {
                 Err("This is not a valid repeat string")
             }
         })
-        .interact()
-        .unwrap();
+        .interact()?;
 
-    repeat
+    Ok(repeat)
 }
 
-/// Displays given string
-fn user_input_date(displayed_text: &str) -> String {
+/// Asks for a YYYY-MM-DD date, "today", "+3d" and "-7d" are resolved against today
+fn user_input_date(displayed_text: &str) -> Result<String> {
     let date: String = Input::new()
         .with_prompt(displayed_text)
         .validate_with(|input: &str| -> Result<(), &str> {
-            if input.contains("2020") {
-                Ok(())
-            } else {
-                Err("This is not a 2020 date")
+            match datetime::date_plus_n(&datetime::resolve_date(input), 0) {
+                Ok(_) => Ok(()),
+                Err(_) => Err("This is not a YYYY-MM-DD date"),
             }
         })
-        .interact()
-        .unwrap();
+        .interact()?;
 
-    date
+    Ok(datetime::resolve_date(&date))
 }
 
-fn user_input_int(displayed_text: &str) -> i32 {
-    let value: i32 = Input::new().with_prompt(displayed_text).interact().unwrap();
-    value
+/// Like user_input_date, but an empty answer is returned as an empty string
+fn user_input_optional_date(displayed_text: &str) -> Result<String> {
+    let date: String = Input::new()
+        .allow_empty(true)
+        .with_prompt(displayed_text)
+        .validate_with(|input: &str| -> Result<(), &str> {
+            if input.trim().is_empty() {
+                return Ok(());
+            }
+            match datetime::date_plus_n(&datetime::resolve_date(input), 0) {
+                Ok(_) => Ok(()),
+                Err(_) => Err("This is not a YYYY-MM-DD date"),
+            }
+        })
+        .interact()?;
+
+    if date.trim().is_empty() {
+        return Ok(String::new());
+    }
+    Ok(datetime::resolve_date(&date))
+}
+
+fn user_input_int(displayed_text: &str) -> Result<i32> {
+    let value: i32 = Input::new().with_prompt(displayed_text).interact()?;
+    Ok(value)
 }
 
-fn add_task_today(dir: String) -> Result<()> {
+fn add_task_today(dir: String, template: &LineTemplate) -> Result<()> {
     println!("Adding Task...");
-    let name = user_input("Name");
-    let notes = user_input_allow_empty("Notes");
-    let project = user_input("Project");
-    let start = user_input("Start Time");
-    let estimate = user_input_int("Estimate (Minutes)");
+    let name = user_input("Name")?;
+    let notes = user_input_allow_empty("Notes")?;
+    let project = user_input("Project")?;
+    let start = user_input("Start Time")?;
+    let estimate = user_input_int("Estimate (Minutes)")?;
 
     let t = Task {
         id: 0,
@@ -147,7 +189,7 @@ fn add_task_today(dir: String) -> Result<()> {
         notes: notes.trim().to_string(),
         project: project.trim().to_string(),
         start: start.trim().to_string(),
-        estimate: estimate,
+        estimate,
         repeat: "".to_string(),
         next: "".to_string(),
     };
@@ -155,34 +197,33 @@ fn add_task_today(dir: String) -> Result<()> {
     let today = datetime::yyyymmdd_today_plus_n(0).replace("-", "");
     let file_path = [&dir, &today, ".md"].join("");
 
-    append_line_to_file(&file_path, t.to_string())?;
+    append_line_to_file(&file_path, template.render_line(&t))?;
 
     Ok(())
 }
 
 fn append_line_to_file(path: &str, line: String) -> Result<()> {
-    let mut file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(path)
-        .unwrap();
-
-    if let Err(e) = writeln!(file, "\n{}", line) {
-        eprintln!("Couldn't write to file: {}", e);
-    }
+    let mut file = OpenOptions::new().append(true).open(path)?;
+    writeln!(file, "\n{}", line)?;
 
     Ok(())
 }
 
-fn call_add_task(conn: &Connection) -> Result<()> {
+fn call_add_task(conn: &Connection, capacity: Option<i32>) -> Result<()> {
     println!("Adding Task...");
-    let name = user_input("Name");
-    let notes = user_input_allow_empty("Notes");
-    let project = user_input("Project");
-    let start = user_input("Start Time");
-    let estimate = user_input_int("Estimate (Minutes)");
-    let repeat = user_input_repeat_string();
-    let next = user_input_date("Date");
+    let name = user_input("Name")?;
+    let notes = user_input_allow_empty("Notes")?;
+    let project = user_input("Project")?;
+    let start = user_input("Start Time")?;
+    let estimate = user_input_int("Estimate (Minutes)")?;
+    let repeat = user_input_repeat_string()?;
+    // One-off tasks may be left undated, routines need a date to repeat from
+    let next = if repeat.trim().is_empty() {
+        user_input_optional_date("Date (empty for undated)")?
+    } else {
+        user_input_date("Date")?
+    };
+    let tags = user_input_allow_empty("Tags (space separated)")?;
 
     let t = Task {
         id: 0,
@@ -191,7 +232,7 @@ fn call_add_task(conn: &Connection) -> Result<()> {
         notes: notes.trim().to_string(),
         project: project.trim().to_string(),
         start: start.trim().to_string(),
-        estimate: estimate,
+        estimate,
         repeat: repeat.trim().to_string(),
         next: next.trim().to_string(),
     };
@@ -199,7 +240,18 @@ fn call_add_task(conn: &Connection) -> Result<()> {
     sql::add_task(conn, t)?;
     let id = sql::get_last_id(conn)?;
     if !notes.trim().is_empty() {
-        sql::add_note(conn, id, next.trim(), "", notes.trim())?;
+        let note_date = if next.is_empty() {
+            datetime::yyyymmdd_today_plus_n(0)
+        } else {
+            next.clone()
+        };
+        sql::add_note(conn, id, &note_date, "", notes.trim())?;
+    }
+    for tag in tags.split_whitespace() {
+        sql::add_tag(conn, &id, tag)?;
+    }
+    if !next.is_empty() {
+        check_capacity(conn, &next, capacity)?;
     }
 
     Ok(())
@@ -214,6 +266,8 @@ fn view_tasks_menu(conn: &Connection) -> Result<()> {
         "Filter Routines",
         "Filter by Repeat",
         "Filter by Status",
+        "Custom Filter",
+        "Saved Filters",
         "quit",
     ];
 
@@ -221,17 +275,18 @@ fn view_tasks_menu(conn: &Connection) -> Result<()> {
         .with_prompt("View Tasks")
         .items(&selected[..])
         .default(0)
-        .interact()
-        .unwrap();
+        .interact()?;
 
     match selection {
-        0 => filter_by_print(&conn, Ok("active"))?,
-        1 => filter_by_print(&conn, Ok("date"))?,
-        2 => filter_by_print(&conn, Ok("project"))?,
-        3 => filter_by_print(&conn, Ok("routine"))?,
-        4 => filter_by_print(&conn, Ok("repeat"))?,
-        5 => filter_by_print(&conn, Ok("status"))?,
-        6 => (),
+        0 => filter_by_print(conn, Ok("active"))?,
+        1 => filter_by_print(conn, Ok("date"))?,
+        2 => filter_by_print(conn, Ok("project"))?,
+        3 => filter_by_print(conn, Ok("routine"))?,
+        4 => filter_by_print(conn, Ok("repeat"))?,
+        5 => filter_by_print(conn, Ok("status"))?,
+        6 => custom_filter_print(conn)?,
+        7 => saved_filters_menu(conn)?,
+        8 => (),
         _ => println!("Something went wrong"),
     }
 
@@ -241,13 +296,13 @@ fn view_tasks_menu(conn: &Connection) -> Result<()> {
 fn filter_by_print(conn: &Connection, by: Result<&str>) -> Result<()> {
     let task_vector = match by {
         Ok("active") => sql::filter_by_status(conn, "ACTIVE")?,
-        Ok("date") => sql::filter_by_date(conn, &user_input_date("Date"))?,
-        Ok("project") => sql::filter_by_project(conn, user_input("Project"))?,
+        Ok("date") => sql::filter_by_date(conn, &user_input_date("Date")?)?,
+        Ok("project") => sql::filter_by_project(conn, user_input("Project")?)?,
         Ok("routine") => sql::filter_by_routine(conn)?,
-        Ok("repeat") => sql::filter_by_repeat(conn, user_input("Repeat"))?,
-        Ok("status") => sql::filter_by_status(conn, &user_input("Status"))?,
-        Ok(_) => panic!(),
-        Err(_err) => panic!(),
+        Ok("repeat") => sql::filter_by_repeat(conn, user_input("Repeat")?)?,
+        Ok("status") => sql::filter_by_status(conn, &user_input("Status")?)?,
+        Ok(other) => return Err(Error::Validation(format!("unknown filter {}", other))),
+        Err(err) => return Err(err),
     };
 
     print_task_vector(&task_vector)?;
@@ -256,37 +311,113 @@ fn filter_by_print(conn: &Connection, by: Result<&str>) -> Result<()> {
     Ok(())
 }
 
-fn select_task_action(conn: &Connection, task_vector: &Vec<Task>) -> Result<()> {
+/// Asks for every filter criterion, empty inputs are ignored
+fn user_input_saved_filter() -> Result<SavedFilter> {
+    Ok(SavedFilter {
+        name: "".to_string(),
+        status: user_input_allow_empty("Status")?.trim().to_string(),
+        project: user_input_allow_empty("Project")?.trim().to_string(),
+        tags: user_input_allow_empty("Tags (space separated)")?.trim().to_string(),
+        next_from: user_input_allow_empty("From Date (date, today or +Nd/-Nd)")?.trim().to_string(),
+        next_to: user_input_allow_empty("To Date (date, today or +Nd/-Nd)")?.trim().to_string(),
+        repeat: user_input_allow_empty("Repeat (routine, none or a repeat string)")?.trim().to_string(),
+        text: user_input_allow_empty("Search Text")?.trim().to_string(),
+    })
+}
+
+/// Runs a custom filter and offers to save it under a name
+fn custom_filter_print(conn: &Connection) -> Result<()> {
+    let mut saved_filter = user_input_saved_filter()?;
+    let task_vector = saved_filter.to_query().run(conn)?;
+    print_task_vector(&task_vector)?;
+
+    if Confirm::new().with_prompt("Save this filter?").interact()? {
+        saved_filter.name = user_input("Filter Name")?.trim().to_string();
+        sql::save_filter(conn, &saved_filter)?;
+    }
+
+    select_task_action(conn, &task_vector)?;
+
+    Ok(())
+}
+
+/// Lists saved filters with the number of matching tasks
+fn saved_filters_menu(conn: &Connection) -> Result<()> {
+    let saved_filters = sql::get_saved_filters(conn)?;
+    if saved_filters.is_empty() {
+        println!("No saved filters, save one from Custom Filter");
+        return Ok(());
+    }
+
+    let mut items = Vec::new();
+    for saved_filter in &saved_filters {
+        let count = saved_filter.to_query().run(conn)?.len();
+        items.push(format!("{} ({})", saved_filter.name, count));
+    }
+    items.push("Delete a Saved Filter".to_string());
+    items.push("quit".to_string());
+
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Saved Filters")
+        .items(&items[..])
+        .default(0)
+        .interact()?;
+
+    if selection < saved_filters.len() {
+        let task_vector = saved_filters[selection].to_query().run(conn)?;
+        print_task_vector(&task_vector)?;
+        select_task_action(conn, &task_vector)?;
+    } else if selection == saved_filters.len() {
+        let name = user_input("Filter Name")?;
+        sql::delete_saved_filter(conn, name.trim())?;
+    }
+
+    Ok(())
+}
+
+/// Prints the tasks matching the saved filter with given name
+pub fn print_saved_filter(conn: &Connection, name: &str) -> Result<()> {
+    let saved_filters = sql::get_saved_filters(conn)?;
+    match saved_filters.iter().find(|f| f.name == name) {
+        Some(saved_filter) => print_task_vector(&saved_filter.to_query().run(conn)?)?,
+        None => return Err(Error::Validation(format!("no saved filter named {}", name))),
+    }
+
+    Ok(())
+}
+
+fn select_task_action(conn: &Connection, task_vector: &[Task]) -> Result<()> {
     let selected = &["Perform action on tasks", "quit"];
     let selection = Select::with_theme(&ColorfulTheme::default())
         .with_prompt("Select")
         .items(&selected[..])
         .default(0)
-        .interact()
-        .unwrap();
+        .interact()?;
 
     if selection == 0 {
-        bulk_edit_menu(conn, &task_vector)?;
+        bulk_edit_menu(conn, task_vector)?;
     }
 
     Ok(())
 }
 
-fn bulk_edit_menu(conn: &Connection, task_vector: &Vec<Task>) -> Result<()> {
+fn bulk_edit_menu(conn: &Connection, task_vector: &[Task]) -> Result<()> {
     let mut all_ids: Vec<i32> = Vec::new();
     for entry in task_vector.iter() {
         all_ids.push(entry.id);
     }
 
-    let selections = user_input("Enter 'all' or space seperated ID numbers");
+    let selections = user_input("Enter 'all' or space seperated ID numbers")?;
 
-    let mut selected_ids = Vec::new();
-    if selections == "all" {
-        selected_ids = all_ids;
+    let selected_ids: Vec<i32> = if selections == "all" {
+        all_ids
     } else {
-        let mapped = selections.split_whitespace().map(|s| s.parse::<i32>().unwrap());
-        selected_ids = mapped.collect();
-    }
+        let mapped = selections.split_whitespace().map(|s| {
+            s.parse::<i32>()
+                .map_err(|_| Error::Validation(format!("'{}' is not a task ID", s)))
+        });
+        mapped.collect::<Result<Vec<i32>>>()?
+    };
 
     multiple_task_actions_menu(conn, &selected_ids)?;
 
@@ -296,7 +427,7 @@ fn bulk_edit_menu(conn: &Connection, task_vector: &Vec<Task>) -> Result<()> {
     Ok(())
 }
 
-fn multiple_task_actions_menu(conn: &Connection, id_vector: &Vec<i32>) -> Result<()> {
+fn multiple_task_actions_menu(conn: &Connection, id_vector: &[i32]) -> Result<()> {
     let selected = &[
         "Modify Date",
         "Modify Start Time",
@@ -304,6 +435,7 @@ fn multiple_task_actions_menu(conn: &Connection, id_vector: &Vec<i32>) -> Result
         "Modify/Add Notes",
         "Modify Estimates",
         "Modify Status",
+        "Modify Tags",
         "Delete Task",
         "quit",
     ];
@@ -314,13 +446,15 @@ fn multiple_task_actions_menu(conn: &Connection, id_vector: &Vec<i32>) -> Result
         .interact();
 
     match selection {
-        Ok(0) => user_input_bulk_edit_date(conn, &id_vector)?,
-        Ok(1) => user_input_bulk_edit_start(conn, &id_vector)?,
-        Ok(2) => user_input_bulk_edit_project(conn, &id_vector)?,
-        Ok(3) => user_input_bulk_edit_notes(conn, &id_vector)?,
-        Ok(4) => user_input_bulk_edit_estimates(conn, &id_vector)?,
-        Ok(5) => user_input_bulk_edit_status(conn, &id_vector)?,
-        Ok(6) => bulk_delete(conn, &id_vector)?,
+        Ok(0) => user_input_bulk_edit_date(conn, id_vector)?,
+        Ok(1) => user_input_bulk_edit_start(conn, id_vector)?,
+        Ok(2) => user_input_bulk_edit_project(conn, id_vector)?,
+        Ok(3) => user_input_bulk_edit_notes(conn, id_vector)?,
+        Ok(4) => user_input_bulk_edit_estimates(conn, id_vector)?,
+        Ok(5) => user_input_bulk_edit_status(conn, id_vector)?,
+        Ok(6) => user_input_bulk_edit_tags(conn, id_vector)?,
+        Ok(7) => bulk_delete(conn, id_vector)?,
+        Ok(8) => (),
         Ok(_) => println!("Something went wrong"),
         Err(_err) => println!("Error"),
     }
@@ -328,53 +462,41 @@ fn multiple_task_actions_menu(conn: &Connection, id_vector: &Vec<i32>) -> Result
     Ok(())
 }
 
-fn user_input_bulk_edit_date(conn: &Connection, id_vec: &Vec<i32>) -> Result<()> {
-    let date = user_input_date("New Date");
+fn user_input_bulk_edit_date(conn: &Connection, id_vec: &[i32]) -> Result<()> {
+    let date = user_input_date("New Date")?;
 
-    for id in id_vec.iter() {
-        sql::modify_date(conn, id, &date)?;
-    }
-
-    Ok(())
+    bulk_apply(conn, id_vec, |tx, id| sql::modify_date(tx, id, &date))
 }
 
-fn user_input_bulk_edit_start(conn: &Connection, id_vec: &Vec<i32>) -> Result<()> {
-    let start = user_input("New Start Time");
+fn user_input_bulk_edit_start(conn: &Connection, id_vec: &[i32]) -> Result<()> {
+    let start = user_input("New Start Time")?;
 
-    for id in id_vec.iter() {
-        sql::modify_start(conn, id, &start)?;
-    }
-
-    Ok(())
+    bulk_apply(conn, id_vec, |tx, id| sql::modify_start(tx, id, &start))
 }
 
-fn user_input_bulk_edit_project(conn: &Connection, id_vec: &Vec<i32>) -> Result<()> {
-    let project = user_input("New Project");
+fn user_input_bulk_edit_project(conn: &Connection, id_vec: &[i32]) -> Result<()> {
+    let project = user_input("New Project")?;
 
-    for id in id_vec.iter() {
-        sql::modify_project(conn, id, &project)?;
-    }
-
-    Ok(())
+    bulk_apply(conn, id_vec, |tx, id| sql::modify_project(tx, id, &project))
 }
 
-fn user_input_bulk_edit_notes(conn: &Connection, id_vec: &Vec<i32>) -> Result<()> {
-    let notes = sql::get_all_notes(conn, &id_vec.to_vec())?;
+fn user_input_bulk_edit_notes(conn: &Connection, id_vec: &[i32]) -> Result<()> {
+    let notes = sql::get_all_notes(conn, id_vec)?;
     print_note_vector(&notes)?;
 
-    let id = user_input_int("Task ID");
-    let start = user_input_date("Start Date");
+    let id = user_input_int("Task ID")?;
+    let start = user_input_date("Start Date")?;
 
     let note_count = sql::note_num_exist(conn, id, &start)?;
     
     if note_count == 0 {
-        let new_note = user_input("New Note");
+        let new_note = user_input("New Note")?;
         sql::add_note(conn, id, start.trim(), "", new_note.trim())?
     }
     else if note_count > 0 {
-        let selection = user_input("Modify: mod or Delete: del");
+        let selection = user_input("Modify: mod or Delete: del")?;
         if selection == "mod" {
-            let new_note = user_input("New Note");
+            let new_note = user_input("New Note")?;
             sql::modify_notes(conn, &id, &start, &new_note)?;
         }
         else if selection == "del" {
@@ -385,26 +507,27 @@ fn user_input_bulk_edit_notes(conn: &Connection, id_vec: &Vec<i32>) -> Result<()
         println!("Something Wrong");
     }
 
-    let notes = sql::get_all_notes(conn, &id_vec.to_vec())?;
+    let notes = sql::get_all_notes(conn, id_vec)?;
     print_note_vector(&notes)?;
 
     Ok(())
 }
 
-fn user_input_bulk_edit_estimates(conn: &Connection, id_vec: &Vec<i32>) -> Result<()> {
-    let estimates = user_input("Estimates").parse::<i32>().unwrap();
-
-    for id in id_vec.iter() {
-        sql::modify_estimates(conn, id, &estimates)?;
-    }
+fn user_input_bulk_edit_estimates(conn: &Connection, id_vec: &[i32]) -> Result<()> {
+    let input = user_input("Estimates")?;
+    let estimates = input
+        .trim()
+        .parse::<i32>()
+        .map_err(|_| Error::Validation(format!("'{}' is not a number of minutes", input)))?;
 
-    Ok(())
+    bulk_apply(conn, id_vec, |tx, id| sql::modify_estimates(tx, id, &estimates))
 }
 
-fn user_input_bulk_edit_status(conn: &Connection, id_vec: &Vec<i32>) -> Result<()> {
+fn user_input_bulk_edit_status(conn: &Connection, id_vec: &[i32]) -> Result<()> {
     let selected = &[
         "ACTIVE",
         "INACTIVE",
+        "DONE",
     ];
 
     let selection = Select::with_theme(&ColorfulTheme::default())
@@ -416,81 +539,291 @@ fn user_input_bulk_edit_status(conn: &Connection, id_vec: &Vec<i32>) -> Result<(
     let status = match selection {
         Ok(0) => "ACTIVE",
         Ok(1) => "INACTIVE",
+        Ok(2) => "DONE",
         Ok(_) => "ACTIVE",
         Err(_) => "ACTIVE",
     };
 
-    for id in id_vec.iter() {
-        sql::modify_status(conn, id, &status)?;
+    bulk_apply(conn, id_vec, |tx, id| sql::modify_status(tx, id, status))
+}
+
+fn user_input_bulk_edit_tags(conn: &Connection, id_vec: &[i32]) -> Result<()> {
+    let tag = user_input("Tag")?;
+    let selection = user_input("Add: add or Remove: del")?;
+
+    if selection == "add" {
+        bulk_apply(conn, id_vec, |tx, id| sql::add_tag(tx, id, tag.trim()))?;
+    } else if selection == "del" {
+        bulk_apply(conn, id_vec, |tx, id| sql::delete_tag(tx, id, tag.trim()))?;
     }
 
     Ok(())
 }
 
-fn bulk_delete(conn: &Connection, id_vec: &Vec<i32>) -> Result<()> {
-    for id in id_vec.iter() {
-        sql::delete_task_by_id(conn, id)?;
+fn bulk_delete(conn: &Connection, id_vec: &[i32]) -> Result<()> {
+    bulk_apply(conn, id_vec, sql::delete_task_by_id)
+}
+
+/// Shows active one-off tasks whose date passed without a log entry
+/// and moves all of them to today or a chosen date
+fn rollover_overdue_tasks(conn: &Connection) -> Result<()> {
+    let today = datetime::yyyymmdd_today_plus_n(0);
+    let overdue = sql::filter_by_overdue(conn, &today)?;
+    if overdue.is_empty() {
+        return Ok(());
     }
+
+    println!("{} overdue tasks", overdue.len());
+    print_task_vector(&overdue)?;
+
+    let selected = &["Move to Today", "Move to Another Date", "Leave Them"];
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Overdue Tasks")
+        .items(&selected[..])
+        .default(0)
+        .interact()?;
+
+    let date = match selection {
+        0 => today,
+        1 => user_input_date("New Date")?,
+        _ => return Ok(()),
+    };
+
+    let id_vec: Vec<i32> = overdue.iter().map(|t| t.id).collect();
+    bulk_apply(conn, &id_vec, |tx, id| sql::modify_date(tx, id, &date))
+}
+
+/// Applies given edit to every selected task in a single transaction
+/// If any edit fails, all of them are rolled back and the error is returned
+fn bulk_apply<F>(conn: &Connection, id_vec: &[i32], edit: F) -> Result<()>
+where
+    F: Fn(&Connection, &i32) -> rusqlite::Result<()>,
+{
+    sql::in_transaction(conn, |tx| {
+        for id in id_vec.iter() {
+            edit(tx, id)?;
+        }
+        Ok(())
+    })?;
+
     Ok(())
 }
 
-fn call_generate_daily_plan(conn: &Connection, dir: String) -> Result<()> {
-    let date_vec = datetime::days_range(0, 2);
+fn call_generate_daily_plan(
+    conn: &Connection,
+    dir: String,
+    template: &LineTemplate,
+    schedule: Option<&Schedule>,
+    capacity: Option<i32>,
+) -> Result<()> {
+    let mut date_vec = datetime::days_range(0, 7);
+    date_vec.push("Other date".to_string());
     let date_slice: &[String] = &date_vec;
 
     let selection = Select::with_theme(&ColorfulTheme::default())
         .with_prompt("Generate Plan for Date")
         .items(date_slice)
         .default(1)
-        .interact();
+        .interact()?;
+
+    let target_date = if selection == date_slice.len() - 1 {
+        user_input_date("Date")?
+    } else {
+        date_slice[selection].clone()
+    };
 
-    let n = match selection {
-        Ok(0) => 0,
-        Ok(1) => 1,
-        Ok(_) => panic!("Something wrong"),
-        Err(_err) => panic!("Something Wrong"),
+    check_capacity(conn, &target_date, capacity)?;
+    let file_path = plan_file_path(&dir, &target_date);
+
+    let existing = if Path::new(&file_path).exists() {
+        let selected = &["Merge into Existing File", "Overwrite", "Cancel"];
+        let selection = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("{} already exists", &file_path))
+            .items(&selected[..])
+            .default(0)
+            .interact()?;
+        match selection {
+            0 => Some(std::fs::read_to_string(&file_path)?),
+            1 => None,
+            _ => {
+                println!("nevermind then :(");
+                return Ok(());
+            }
+        }
+    } else {
+        println!("{}", file_path);
+        None
     };
 
-    let target_date = datetime::yyyymmdd_today_plus_n(n);
+    let layout = match &existing {
+        Some(existing) => PlanLayout::Merge(existing),
+        None => user_input_plan_layout()?,
+    };
+    let (plan_string, unscheduled) = sql::generate_plan(conn, &target_date, template, schedule, layout)?;
+    print_unscheduled(&unscheduled);
+    save_string_to_file(plan_string, &file_path)?;
 
-    let plan_string = match n {
-        0 => sql::generate_today_plan(conn)?,
-        1 => sql::generate_tomorrow_plan(conn)?,
-        _ => panic!("plan_string something wrong"),
+    Ok(())
+}
+
+/// Writes the plan for a date without asking, an existing file is merged with the new plan
+/// New files are grouped by project when grouped is set
+pub fn generate_daily_plan(
+    conn: &Connection,
+    dir: &str,
+    date: &str,
+    template: &LineTemplate,
+    schedule: Option<&Schedule>,
+    capacity: Option<i32>,
+    grouped: bool,
+) -> Result<()> {
+    let date = datetime::resolve_date(date);
+    datetime::date_plus_n(&date, 0)?;
+
+    let file_path = plan_file_path(dir, &date);
+    let existing = if Path::new(&file_path).exists() {
+        Some(std::fs::read_to_string(&file_path)?)
+    } else {
+        None
     };
 
-    let file_path = [dir, target_date.replace("-", ""), ".md".to_string()].join("");
-    
+    let layout = match &existing {
+        Some(existing) => PlanLayout::Merge(existing),
+        None if grouped => PlanLayout::Grouped,
+        None => PlanLayout::Flat,
+    };
+
+    capacity_warning(conn, &date, capacity)?;
+    let (plan_string, unscheduled) = sql::generate_plan(conn, &date, template, schedule, layout)?;
+    print_unscheduled(&unscheduled);
+    save_string_to_file(plan_string, &file_path)?;
+
+    Ok(())
+}
+
+/// Writes the weekly plan for this week, next week or the week of a chosen date
+fn call_generate_week_plan(conn: &Connection, dir: &str, template: &LineTemplate) -> Result<()> {
+    let selected = &["This Week", "Next Week", "Other date"];
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Generate Weekly Plan for")
+        .items(&selected[..])
+        .default(1)
+        .interact()?;
+
+    let date = match selection {
+        0 => datetime::yyyymmdd_today_plus_n(0),
+        1 => datetime::yyyymmdd_today_plus_n(7),
+        _ => user_input_date("Any date in the week")?,
+    };
+
+    let (week, _) = datetime::iso_week(&date)?;
+    let file_path = [dir, &week, ".md"].join("");
+    let plan_string = sql::generate_week_plan(conn, &date, template)?;
+
     if Path::new(&file_path).exists() {
         let message = format!("Do you want to overwrite {}?", &file_path);
-        if Confirm::new().with_prompt(message).interact().unwrap() {
-            save_string_to_file(plan_string, &file_path)?;
-        } else {
+        if !Confirm::new().with_prompt(message).interact()? {
             println!("nevermind then :(");
+            return Ok(());
         }
-    } else {
-        println!("{}", file_path);
-        save_string_to_file(plan_string, &file_path)?;
     }
+    save_string_to_file(plan_string, &file_path)?;
 
     Ok(())
 }
 
-/// Save given string at given path
-fn save_string_to_file(s: String, path: &str) -> Result<()> {
-    let mut file = match File::create(path) {
-        Err(why) => panic!("couldn't create: {}", why),
-        Ok(file) => file,
+/// Prints a warning when the estimates on a date are more than the capacity
+/// Returns true when the date is overloaded
+fn capacity_warning(conn: &Connection, date: &str, capacity: Option<i32>) -> Result<bool> {
+    let capacity = match capacity {
+        Some(capacity) => capacity,
+        None => return Ok(false),
     };
-    match file.write_all(s.as_bytes()) {
-        Err(why) => panic!("couldn't write: {}", why),
-        Ok(_) => println!("successfully wrote: {:?}", file),
+    let load = sql::planned_minutes(conn, date)?;
+    if load > capacity {
+        eprintln!(
+            "Warning: {} has {} min planned, {} min over the daily capacity of {} min",
+            date,
+            load,
+            load - capacity,
+            capacity
+        );
+    }
+
+    Ok(load > capacity)
+}
+
+/// Warns when a date is overloaded and offers to move tasks to the next days with room
+fn check_capacity(conn: &Connection, date: &str, capacity: Option<i32>) -> Result<()> {
+    if !capacity_warning(conn, date, capacity)? {
+        return Ok(());
+    }
+    let moves = sql::overload_moves(conn, date, capacity.unwrap_or_default())?;
+    if moves.is_empty() {
+        println!("No tasks can be moved to a day with room");
+        return Ok(());
+    }
+
+    println!(
+        "One-off tasks without a start time go first, then timed ones, newest first, \
+         to the first of the next {} days with room:",
+        sql::CAPACITY_SEARCH_DAYS
+    );
+    for (task, new_date) in &moves {
+        println!("{} ({} min) -> {}", task.name, task.estimate, new_date);
+    }
+    if Confirm::new().with_prompt("Move these tasks?").interact()? {
+        sql::in_transaction(conn, |tx| {
+            for (task, new_date) in &moves {
+                sql::modify_date(tx, &task.id, new_date)?;
+            }
+            Ok(())
+        })?;
+        println!("Moved {} tasks", moves.len());
+    }
+
+    Ok(())
+}
+
+fn user_input_plan_layout() -> Result<PlanLayout<'static>> {
+    let selected = &["Flat", "Grouped by Project"];
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Plan Layout")
+        .items(&selected[..])
+        .default(0)
+        .interact()?;
+
+    match selection {
+        0 => Ok(PlanLayout::Flat),
+        _ => Ok(PlanLayout::Grouped),
     }
+}
+
+/// Warns about flexible tasks that are left without a start time
+fn print_unscheduled(unscheduled: &[Task]) {
+    for task in unscheduled {
+        eprintln!(
+            "Warning: {} ({} min) doesn't fit into working hours and has no start time",
+            task.name, task.estimate
+        );
+    }
+}
+
+fn plan_file_path(dir: &str, date: &str) -> String {
+    [dir, &date.replace("-", ""), ".md"].join("")
+}
+
+/// Save given string at given path
+fn save_string_to_file(s: String, path: &str) -> Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(s.as_bytes())?;
+    println!("successfully wrote: {:?}", file);
+
     Ok(())
 }
 
 /// Prints tasks given a vector with Task structures
-fn print_task_vector(task_vector: &Vec<Task>) -> Result<()> {
+fn print_task_vector(task_vector: &[Task]) -> Result<()> {
     let mut table = Table::new();
     table.style = TableStyle::extended();
     table.add_row(Row::new(vec![
@@ -502,7 +835,7 @@ fn print_task_vector(task_vector: &Vec<Task>) -> Result<()> {
     for task in task_vector {
         let t = task;
         table.add_row(Row::new(vec![
-            TableCell::new_with_alignment(&t.id, 1, Alignment::Left),
+            TableCell::new_with_alignment(t.id, 1, Alignment::Left),
             TableCell::new_with_alignment(&t.name, 1, Alignment::Left),
             TableCell::new_with_alignment(&t.project, 2, Alignment::Center),
             TableCell::new_with_alignment(&t.next, 2, Alignment::Center),
@@ -514,7 +847,7 @@ fn print_task_vector(task_vector: &Vec<Task>) -> Result<()> {
 }
 
 /// Prints notes given a vector with Note structures
-fn print_note_vector(note_vector: &Vec<Note>) -> Result<()> {
+fn print_note_vector(note_vector: &[Note]) -> Result<()> {
     let mut table = Table::new();
     table.style = TableStyle::extended();
     table.set_max_column_widths(vec![(0, 5), (1, 40), (2, 31), (3, 10)]);
@@ -527,7 +860,7 @@ fn print_note_vector(note_vector: &Vec<Note>) -> Result<()> {
     for note in note_vector {
         let n = note;
         table.add_row(Row::new(vec![
-            TableCell::new_with_alignment(&n.id, 1, Alignment::Left),
+            TableCell::new_with_alignment(n.id, 1, Alignment::Left),
             TableCell::new_with_alignment(&n.notetext, 1, Alignment::Left),
             TableCell::new_with_alignment(&n.name, 2, Alignment::Left),
             TableCell::new_with_alignment(&n.start, 2, Alignment::Center),
@@ -539,11 +872,9 @@ fn print_note_vector(note_vector: &Vec<Note>) -> Result<()> {
     Ok(())
 }
 
-fn markdown_log_to_database(conn: &Connection, dir: String) -> Result<()> {
-    let date = user_input_date("Date to save to database");
-    let log_filename = date.replace("-", "") + ".md";
-    let log_path = dir + &log_filename;
-    sql::log_to_database(conn, log_path, date).ok();
+fn markdown_log_to_database(conn: &Connection, dir: String, template: &LineTemplate) -> Result<()> {
+    let date = user_input_date("Date to save to database")?;
+    import_markdown_log(conn, &dir, date, template)?;
 
     Ok(())
 }
@@ -556,26 +887,12 @@ fn generate_daily_report(conn: &Connection, dir: String) -> Result<()> {
         .with_prompt("Generate Plan for Date")
         .items(date_slice)
         .default(6)
-        .interact();
-
-    let mut n = -1;
-    match &selection {
-        Ok(0) => n = -7,
-        Ok(1) => n = -6,
-        Ok(2) => n = -5,
-        Ok(3) => n = -4,
-        Ok(4) => n = -3,
-        Ok(5) => n = -2,
-        Ok(6) => n = -1,
-        Ok(7) => n = 0,
-        Ok(_) => println!("Something went wrong"),
-        Err(_err) => println!("Error"),
-    }
+        .interact()?;
 
-    let log_vector = sql::daily_report_log_vector(conn, selection, date_slice)?;
+    let log_vector = sql::daily_report_log_vector(conn, &date_slice[selection])?;
     let table_string = log_vector_to_markdown_table_string(log_vector);
 
-    let filename = datetime::yyyymmdd_today_plus_n(n).replace("-", "");
+    let filename = date_slice[selection].replace("-", "");
     let path = format!("{}{}{}{}", dir, "log\\", filename, "_log.md");
 
     save_string_to_file(table_string, &path)?;
@@ -583,31 +900,712 @@ fn generate_daily_report(conn: &Connection, dir: String) -> Result<()> {
     Ok(())
 }
 
+/// Estimated and logged minutes added up over several log rows
+/// Error is actual minus estimate, so a positive error means the work took longer than planned
+#[derive(Debug, Default)]
+struct Accuracy {
+    entries: i32,
+    estimate: i64,
+    actual: i64,
+    over: i32,
+    under: i32,
+}
+
+impl Accuracy {
+    fn add(&mut self, estimate: i64, actual: i64) {
+        self.entries += 1;
+        self.estimate += estimate;
+        self.actual += actual;
+        if estimate > actual {
+            self.over += 1;
+        } else if estimate < actual {
+            self.under += 1;
+        }
+    }
+
+    /// Entries, Estimate, Actual, Mean Error, Overestimated, Underestimated, Actual / Estimate
+    fn columns(&self) -> Vec<String> {
+        let mean_error = (self.actual - self.estimate) as f64 / f64::from(self.entries.max(1));
+        let ratio = if self.estimate > 0 {
+            format!("{:.2}", self.actual as f64 / self.estimate as f64)
+        } else {
+            "-".to_string()
+        };
+        vec![
+            self.entries.to_string(),
+            self.estimate.to_string(),
+            self.actual.to_string(),
+            format!("{:+.1}", mean_error),
+            self.over.to_string(),
+            self.under.to_string(),
+            ratio,
+        ]
+    }
+}
+
+fn estimate_accuracy_report_menu(conn: &Connection, dir: &str) -> Result<()> {
+    let from = user_input_date("From Date")?;
+    let to = user_input_date("To Date")?;
+    estimate_accuracy_report(conn, dir, &from, &to)?;
+
+    Ok(())
+}
+
+/// Compares estimates with logged minutes per entry, per project and per task name
+/// and writes the tables to the log directory
+/// Rows without an estimate or with times that can't be read are left out
+pub fn estimate_accuracy_report(conn: &Connection, dir: &str, from: &str, to: &str) -> Result<()> {
+    datetime::days_between(from, to)?;
+    let log_vector = sql::range_log_vector(conn, from, to)?;
+
+    let summary_header = vec![
+        "Entries", "Estimate", "Actual", "Mean Error", "Overestimated", "Underestimated",
+        "Actual / Estimate",
+    ];
+    let mut entry_table = comfy_table::Table::new();
+    entry_table
+        .load_preset(ASCII_MARKDOWN)
+        .set_header(vec!["Date", "Task", "Project", "Estimate", "Actual", "Error"]);
+
+    let mut total = Accuracy::default();
+    let mut by_project: std::collections::BTreeMap<String, Accuracy> = Default::default();
+    let mut by_name: std::collections::BTreeMap<String, Accuracy> = Default::default();
+    let mut left_out = 0;
+
+    for log in &log_vector {
+        let actual = match datetime::get_session_minutes(&log.sessions) {
+            Ok(minutes) if log.estimate > 0 => minutes,
+            _ => {
+                left_out += 1;
+                continue;
+            }
+        };
+        let estimate = i64::from(log.estimate);
+
+        entry_table.add_row(vec![
+            log.date.clone(),
+            log.name.clone(),
+            log.project.clone(),
+            estimate.to_string(),
+            actual.to_string(),
+            format!("{:+}", actual - estimate),
+        ]);
+        total.add(estimate, actual);
+        by_project.entry(log.project.clone()).or_default().add(estimate, actual);
+        by_name.entry(log.name.clone()).or_default().add(estimate, actual);
+    }
+
+    let mut project_table = comfy_table::Table::new();
+    let mut header = vec!["Project"];
+    header.extend(&summary_header);
+    project_table.load_preset(ASCII_MARKDOWN).set_header(header);
+    for (project, accuracy) in &by_project {
+        let mut row = vec![project.clone()];
+        row.extend(accuracy.columns());
+        project_table.add_row(row);
+    }
+
+    let mut name_table = comfy_table::Table::new();
+    let mut header = vec!["Task"];
+    header.extend(&summary_header);
+    name_table.load_preset(ASCII_MARKDOWN).set_header(header);
+    for (name, accuracy) in &by_name {
+        let mut row = vec![name.clone()];
+        row.extend(accuracy.columns());
+        name_table.add_row(row);
+    }
+
+    let mut total_table = comfy_table::Table::new();
+    total_table.load_preset(ASCII_MARKDOWN).set_header(summary_header);
+    total_table.add_row(total.columns());
+
+    let mut report = format!("# Estimate vs Actual {} to {}\n\n{}\n", from, to, total_table);
+    report.push_str(&format!("\n## By Project\n\n{}\n", project_table));
+    report.push_str(&format!("\n## By Task\n\n{}\n", name_table));
+    report.push_str(&format!("\n## Entries\n\n{}\n", entry_table));
+    if left_out > 0 {
+        report.push_str(&format!(
+            "\n{} log rows without an estimate or readable times are left out\n",
+            left_out
+        ));
+    }
+
+    let filename = format!("{}_{}_accuracy.md", from.replace("-", ""), to.replace("-", ""));
+    let path = format!("{}{}{}", dir, "log\\", filename);
+    save_string_to_file(report, &path)?;
+
+    Ok(())
+}
+
+/// Durations that can't be computed are shown as ? and printed as warnings
+/// Rows whose sessions overlap list the tasks they overlap with
 fn log_vector_to_markdown_table_string(log_vector: Vec<Log>) -> String {
     let mut table = comfy_table::Table::new();
-    table
-        .load_preset(ASCII_MARKDOWN)
-        .set_header(vec!["Start", "End", "Duration", "Task", "Review"]);
+    table.load_preset(ASCII_MARKDOWN).set_header(vec![
+        "Start", "End", "Duration", "Task", "Review", "Unplanned", "Overlaps",
+    ]);
 
-    for log in log_vector {
+    let conflicts = schedule::log_conflicts(&log_vector);
+    for (i, log) in log_vector.iter().enumerate() {
         let mut tmp_vec = Vec::new();
         tmp_vec.push(&log.start);
         tmp_vec.push(&log.end);
-        let duration = datetime::get_duration(&log.start, &log.end);
+        let duration = match datetime::get_session_minutes(&log.sessions) {
+            Ok(minutes) => minutes.to_string(),
+            Err(e) => {
+                eprintln!("Warning: {} {} {}: {}", log.date, log.start, log.name, e);
+                "?".to_string()
+            }
+        };
         tmp_vec.push(&duration);
         tmp_vec.push(&log.name);
         tmp_vec.push(&log.review);
+        let unplanned = if log.planned { "" } else { "yes" }.to_string();
+        tmp_vec.push(&unplanned);
+        let overlaps = conflicts
+            .iter()
+            .filter_map(|&(a, b)| {
+                if a == i {
+                    Some(log_vector[b].name.as_str())
+                } else if b == i {
+                    Some(log_vector[a].name.as_str())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<&str>>()
+            .join(", ");
+        tmp_vec.push(&overlaps);
         table.add_row(tmp_vec);
     }
 
     table.to_string()
 }
 
-fn yesterday_log_to_database(conn: &Connection, dir: &str) -> Result<()> {
+fn list_conflicts_menu(conn: &Connection) -> Result<()> {
+    let from = user_input_date("From Date")?;
+    let to = user_input_date("To Date")?;
+    list_conflicts(conn, &from, &to)?;
+
+    Ok(())
+}
+
+/// Prints overlapping plan tasks and overlapping logged sessions for every date in the range
+pub fn list_conflicts(conn: &Connection, from: &str, to: &str) -> Result<()> {
+    let days = datetime::days_between(from, to)?;
+    let mut found = 0;
+
+    for n in 0..=days {
+        let date = datetime::date_plus_n(from, n)?;
+
+        let tasks = sql::filter_by_date_plan(conn, &date)?;
+        for conflict in schedule::describe_task_conflicts(&tasks) {
+            println!("{} plan: {}", date, conflict);
+            found += 1;
+        }
+
+        let logs = sql::daily_report_log_vector(conn, &date)?;
+        for (a, b) in schedule::log_conflicts(&logs) {
+            let (a, b) = (&logs[a], &logs[b]);
+            println!(
+                "{} log: {} {}-{} overlaps {} {}-{}",
+                date, a.name, a.start, a.end, b.name, b.start, b.end
+            );
+            found += 1;
+        }
+    }
+
+    println!("{} conflicts from {} to {}", found, from, to);
+
+    Ok(())
+}
+
+fn export_calendar_menu(conn: &Connection, dir: &str) -> Result<()> {
+    let selected = &["Day", "Date Range", "All Active Tasks"];
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Export to Calendar")
+        .items(&selected[..])
+        .default(0)
+        .interact()?;
+
+    let range = match selection {
+        0 => {
+            let date = user_input_date("Date")?;
+            Some((date.clone(), date))
+        }
+        1 => Some((user_input_date("From Date")?, user_input_date("To Date")?)),
+        _ => None,
+    };
+    export_calendar(conn, dir, range)?;
+
+    Ok(())
+}
+
+/// Writes tasks for a date range, or all active tasks, to an .ics file in the directory
+/// named after the range, like 20200701.ics, 20200701-20200707.ics or tasks.ics
+pub fn export_calendar(conn: &Connection, dir: &str, range: Option<(String, String)>) -> Result<()> {
+    let (tasks, filename) = match &range {
+        Some((from, to)) => {
+            datetime::days_between(from, to)?;
+            let filename = if from == to {
+                from.replace("-", "")
+            } else {
+                format!("{}-{}", from.replace("-", ""), to.replace("-", ""))
+            };
+            (sql::filter_by_calendar_range(conn, from, to)?, filename)
+        }
+        None => (sql::filter_by_status(conn, "ACTIVE")?, "tasks".to_string()),
+    };
+
+    let until = range.as_ref().map(|(_, to)| to.as_str());
+    let (calendar, summary) = ics::tasks_to_ics(&tasks, until);
+    let path = format!("{}{}.ics", dir, filename);
+    save_string_to_file(calendar, &path)?;
+    println!("{}", summary);
+
+    Ok(())
+}
+
+fn import_calendar_menu(conn: &Connection) -> Result<()> {
+    let path = user_input("Path to .ics File")?;
+    let from = user_input_date("From Date")?;
+    let to = user_input_date("To Date")?;
+    import_calendar(conn, path.trim(), &from, &to)?;
+
+    Ok(())
+}
+
+/// Adds the timed events of an .ics file between two dates as fixed-time tasks
+/// Importing the same file again updates those tasks instead of adding them twice
+pub fn import_calendar(conn: &Connection, path: &str, from: &str, to: &str) -> Result<()> {
+    datetime::days_between(from, to)?;
+    let text = std::fs::read_to_string(path)?;
+    let (events, warnings) = ics::parse_ics(&text, from, to);
+    for warning in warnings {
+        eprintln!("{}: {}", path, warning);
+    }
+
+    let summary = sql::calendar_events_to_database(conn, &events, from, to)?;
+    println!("{}", summary);
+
+    Ok(())
+}
+
+fn yesterday_log_to_database(conn: &Connection, dir: &str, template: &LineTemplate) -> Result<()> {
     let date = datetime::yyyymmdd_today_plus_n(-1);
-    let log_filename = date.replace("-", "") + ".md";    
+    import_markdown_log(conn, dir, date, template)?;
+
+    Ok(())
+}
+
+/// Saves the markdown log for given date and prints lines that could not be fully parsed
+fn import_markdown_log(
+    conn: &Connection,
+    dir: &str,
+    date: String,
+    template: &LineTemplate,
+) -> Result<()> {
+    let log_filename = date.replace("-", "") + ".md";
     let log_path = dir.to_string() + &log_filename;
-    sql::log_to_database(conn, log_path, date).ok();
+
+    let mode = if sql::check_log_for_date(conn, &date)? {
+        match user_input_import_mode(&date)? {
+            Some(mode) => mode,
+            None => {
+                println!("Import cancelled");
+                return Ok(());
+            }
+        }
+    } else {
+        ImportMode::Merge
+    };
+
+    let saved = if Confirm::new().with_prompt("Preview entries before saving?").interact()? {
+        preview_markdown_log(conn, &log_path, &date, mode, template)?
+    } else {
+        match sql::log_to_database(conn, log_path.clone(), date.clone(), mode, template) {
+            Ok((summary, warnings)) => {
+                for warning in warnings {
+                    eprintln!("{}: {}", log_path, warning);
+                }
+                println!("{}", summary);
+                true
+            }
+            Err(e) => {
+                eprintln!("Failed to import {}, no log entries were saved: {}", log_path, e);
+                false
+            }
+        }
+    };
+
+    if saved {
+        capture_unplanned_work(conn, &date)?;
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Offers to create a task for every log entry of the date that doesn't match a task
+fn capture_unplanned_work(conn: &Connection, date: &str) -> Result<()> {
+    let unplanned = sql::unplanned_log_vector(conn, date)?;
+    if unplanned.is_empty() {
+        return Ok(());
+    }
+
+    println!("{} entries on {} were not in any task", unplanned.len(), date);
+    let selected = &["Create a task (marked DONE)", "Create a recurring task", "Skip"];
+
+    for log in unplanned {
+        let selection = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Unplanned: [{}] {}", log.project, log.name))
+            .items(&selected[..])
+            .default(2)
+            .interact()?;
+
+        let (status, repeat, next) = match selection {
+            0 => ("DONE".to_string(), "".to_string(), date.to_string()),
+            1 => {
+                let repeat = user_input_repeat_string()?.trim().to_string();
+                let repeat = if repeat.is_empty() { "+1d".to_string() } else { repeat };
+                ("ACTIVE".to_string(), repeat, datetime::date_plus_n(date, 1)?)
+            }
+            _ => continue,
+        };
+        let estimate = datetime::get_session_minutes(&log.sessions).unwrap_or(0) as i32;
+
+        let t = Task {
+            id: 0,
+            status,
+            name: log.name.clone(),
+            notes: log.notes.clone(),
+            project: log.project.clone(),
+            start: log.start.clone(),
+            estimate,
+            repeat,
+            next,
+        };
+        sql::add_task(conn, t)?;
+    }
+
+    Ok(())
+}
+
+/// Asks how to treat rows already stored for the date, None cancels the import
+fn user_input_import_mode(date: &str) -> Result<Option<ImportMode>> {
+    let selected = &[
+        "Replace stored rows with the file",
+        "Merge: update matching rows and add new ones",
+        "Cancel",
+    ];
+
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("{} is already in the database", date))
+        .items(&selected[..])
+        .default(0)
+        .interact()?;
+
+    let mode = match selection {
+        0 => Some(ImportMode::Replace),
+        1 => Some(ImportMode::Merge),
+        _ => None,
+    };
+
+    Ok(mode)
+}
+
+/// Shows the rows an import would create and how they compare to rows already stored
+/// for the date, then saves them once confirmed
+/// Returns whether anything was saved
+fn preview_markdown_log(
+    conn: &Connection,
+    log_path: &str,
+    date: &str,
+    mode: ImportMode,
+    template: &LineTemplate,
+) -> Result<bool> {
+    let text = std::fs::read_to_string(log_path)?;
+    let parsed = parser::parse_log(&text, template);
+    for warning in &parsed.warnings {
+        eprintln!("{}: {}", log_path, warning);
+    }
+
+    let existing = sql::daily_report_log_vector(conn, date)?;
+    let entries: Vec<&LogEntry> = parsed
+        .entries
+        .iter()
+        .filter(|e| e.is_logged())
+        .collect();
+
+    let mut table = Table::new();
+    table.style = TableStyle::extended();
+    table.add_row(Row::new(vec![
+        TableCell::new_with_alignment("Status", 1, Alignment::Left),
+        TableCell::new_with_alignment("Start", 1, Alignment::Center),
+        TableCell::new_with_alignment("End", 1, Alignment::Center),
+        TableCell::new_with_alignment("Sessions", 1, Alignment::Right),
+        TableCell::new_with_alignment("Minutes", 1, Alignment::Right),
+        TableCell::new_with_alignment("Estimate", 1, Alignment::Right),
+        TableCell::new_with_alignment("Project", 1, Alignment::Left),
+        TableCell::new_with_alignment("Task", 1, Alignment::Left),
+    ]));
+    for entry in &entries {
+        let stored = existing
+            .iter()
+            .any(|l| l.name == entry.name && l.start == entry.start());
+        let status = match (stored, mode) {
+            (false, _) => "new",
+            (true, ImportMode::Replace) => "replaces stored row",
+            (true, ImportMode::Merge) => "updates stored row",
+        };
+        let duration = datetime::get_session_minutes(&entry.complete_sessions())
+            .map_or("?".to_string(), |minutes| minutes.to_string());
+        table.add_row(Row::new(vec![
+            TableCell::new_with_alignment(status, 1, Alignment::Left),
+            TableCell::new_with_alignment(entry.start(), 1, Alignment::Center),
+            TableCell::new_with_alignment(entry.end(), 1, Alignment::Center),
+            TableCell::new_with_alignment(entry.complete_sessions().len(), 1, Alignment::Right),
+            TableCell::new_with_alignment(duration, 1, Alignment::Right),
+            TableCell::new_with_alignment(entry.estimate, 1, Alignment::Right),
+            TableCell::new_with_alignment(&entry.project, 1, Alignment::Left),
+            TableCell::new_with_alignment(&entry.name, 1, Alignment::Left),
+        ]));
+    }
+    println!("{}", table.render());
+
+    let only_stored: Vec<&Log> = existing
+        .iter()
+        .filter(|l| !entries.iter().any(|e| e.name == l.name && e.start() == l.start))
+        .collect();
+    let only_stored_count = only_stored.len();
+    if !only_stored.is_empty() {
+        match mode {
+            ImportMode::Replace => println!("Rows stored for {} that will be deleted:", date),
+            ImportMode::Merge => println!("Rows stored for {} that are kept:", date),
+        }
+        for log in only_stored {
+            println!("  {} - {} {}", log.start, log.end, log.name);
+        }
+    }
+
+    let checkboxes = parsed.entries.iter().filter(|e| e.checked.is_some()).count();
+    let deleted = match mode {
+        ImportMode::Replace => only_stored_count,
+        ImportMode::Merge => 0,
+    };
+    if entries.is_empty() && checkboxes == 0 && deleted == 0 {
+        println!("Nothing to save");
+        return Ok(false);
+    }
+
+    let message = format!(
+        "Save {} entries, delete {} stored rows and apply {} checkboxes for {}?",
+        entries.len(),
+        deleted,
+        checkboxes,
+        date
+    );
+    if !Confirm::new().with_prompt(message).interact()? {
+        return Ok(false);
+    }
+    match sql::log_entries_to_database(conn, &parsed.entries, date, mode) {
+        Ok(summary) => {
+            println!("{}", summary);
+            Ok(true)
+        }
+        Err(e) => {
+            eprintln!("Failed to import {}, no log entries were saved: {}", log_path, e);
+            Ok(false)
+        }
+    }
+}
+
+fn backfill_markdown_logs_menu(conn: &Connection, dir: &str, template: &LineTemplate) -> Result<()> {
+    let from = user_input_allow_empty("From Date (empty for no limit)")?;
+    let to = user_input_allow_empty("To Date (empty for no limit)")?;
+    let from = Some(from.trim()).filter(|d| !d.is_empty());
+    let to = Some(to.trim()).filter(|d| !d.is_empty());
+
+    backfill_markdown_logs(conn, dir, template, from, to)?;
+
+    Ok(())
+}
+
+/// Imports every YYYYMMDD.md file in the directory whose date has no log rows yet
+/// and prints which files were imported, skipped or failed
+pub fn backfill_markdown_logs(
+    conn: &Connection,
+    dir: &str,
+    template: &LineTemplate,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<()> {
+    let mut files: Vec<(String, String)> = Vec::new();
+    for dir_entry in std::fs::read_dir(dir)? {
+        let path = dir_entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("md") {
+            continue;
+        }
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        if let Some(date) = datetime::date_from_file_stem(stem) {
+            files.push((date, path.to_string_lossy().to_string()));
+        }
+    }
+    files.sort();
+
+    let mut imported = 0;
+    let mut skipped = 0;
+    let mut failed = 0;
+
+    for (date, path) in files {
+        let before = from.is_some_and(|from| date.as_str() < from);
+        let after = to.is_some_and(|to| date.as_str() > to);
+        if before || after {
+            continue;
+        }
+        if sql::check_log_for_date(conn, &date)? {
+            skipped += 1;
+            continue;
+        }
+
+        match sql::log_to_database(conn, path.clone(), date, ImportMode::Merge, template) {
+            Ok((summary, warnings)) => {
+                imported += 1;
+                println!("imported {}: {}", path, summary);
+                for warning in warnings {
+                    eprintln!("  {}", warning);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("failed {}: {}", path, e);
+            }
+        }
+    }
+
+    println!(
+        "{} files imported, {} skipped (already in database), {} failed",
+        imported, skipped, failed
+    );
+
+    Ok(())
+}
+
+/// Keeps today's log rows in sync with today's markdown file until stopped with Ctrl+C
+/// The file is checked every few seconds and re-imported, replacing the date's rows,
+/// whenever it was saved. Checkboxes are only applied once the day is over, by a final
+/// import when the date changes while watching or by importing the file from the menu
+pub fn watch_markdown_log(conn: &Connection, dir: &str, template: &LineTemplate) -> Result<()> {
+    let interval = std::time::Duration::from_secs(2);
+    let mut last_import: Option<(String, String, std::time::SystemTime)> = None;
+
+    println!("Watching today's markdown log in {}, press Ctrl+C to stop", dir);
+    println!("Checkboxes are applied when the day is over or when the log is imported from the menu");
+    loop {
+        let date = datetime::yyyymmdd_today_plus_n(0);
+        let log_path = dir.to_string() + &date.replace("-", "") + ".md";
+
+        if let Some((last_date, last_path, _)) = &last_import {
+            if *last_date != date {
+                match sql::log_to_database(
+                    conn,
+                    last_path.clone(),
+                    last_date.clone(),
+                    ImportMode::Replace,
+                    template,
+                ) {
+                    Ok((summary, _)) => {
+                        println!("{} {}: {}", datetime::now_hhmm(), last_path, summary)
+                    }
+                    Err(e) => eprintln!("{} {}: {}", datetime::now_hhmm(), last_path, e),
+                }
+                last_import = None;
+            }
+        }
+
+        if let Ok(modified) = std::fs::metadata(&log_path).and_then(|m| m.modified()) {
+            let changed = match &last_import {
+                Some((_, _, last_modified)) => *last_modified != modified,
+                None => true,
+            };
+
+            if changed {
+                match sql::sync_log_to_database(conn, log_path.clone(), date.clone(), template) {
+                    Ok((summary, warnings)) => {
+                        println!("{} {}: {}", datetime::now_hhmm(), log_path, summary);
+                        for warning in warnings {
+                            eprintln!("  {}", warning);
+                        }
+                    }
+                    Err(e) => eprintln!("{} {}: {}", datetime::now_hhmm(), log_path, e),
+                }
+                last_import = Some((date, log_path, modified));
+            }
+        }
+
+        std::thread::sleep(interval);
+    }
+}
+
+fn lint_markdown_log_menu(dir: &str, template: &LineTemplate) -> Result<()> {
+    let date = user_input_date("Date to check")?;
+    let log_path = dir.to_string() + &date.replace("-", "") + ".md";
+    let problems = print_lint_problems(&log_path, template)?;
+    if problems > 0 {
+        println!("{} problems found in {}", problems, log_path);
+    }
+
+    Ok(())
+}
+
+/// Prints every problem found in a plan/log file
+/// Fails when there is at least one, so it can be used from scripts
+pub fn lint_markdown_log(path: &str, template: &LineTemplate) -> Result<()> {
+    let problems = print_lint_problems(path, template)?;
+    if problems > 0 {
+        return Err(Error::Validation(format!("{} problems found in {}", problems, path)));
+    }
+
+    Ok(())
+}
+
+/// Prints the parser warnings for a file and returns how many there were
+fn print_lint_problems(path: &str, template: &LineTemplate) -> Result<usize> {
+    let text = std::fs::read_to_string(path)?;
+    let parsed = parser::parse_log(&text, template);
+
+    for warning in &parsed.warnings {
+        println!("{}: {}", path, warning);
+    }
+    if parsed.warnings.is_empty() {
+        println!("{}: {} entries, no problems found", path, parsed.entries.len());
+    }
+
+    Ok(parsed.warnings.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daily_report_lists_overlapping_rows() {
+        let logs = vec![
+            Log::with_sessions("Report", &[("09:00", "10:00")]),
+            Log::with_sessions("Call", &[("09:30", "09:45")]),
+            Log::with_sessions("Deploy", &[("23:00", "01:00")]),
+        ];
+        let table = log_vector_to_markdown_table_string(logs);
+        let rows: Vec<Vec<&str>> = table
+            .lines()
+            .skip(2)
+            .map(|line| line.split('|').map(str::trim).collect())
+            .collect();
+
+        assert!(table.lines().next().unwrap().contains("Overlaps"));
+        assert_eq!(rows[0][4], "Report");
+        assert_eq!(rows[0][7], "Call");
+        assert_eq!(rows[1][7], "Report");
+        assert_eq!(rows[2][3], "120");
+        assert_eq!(rows[2][7], "");
+    }
+}
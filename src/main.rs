@@ -1,16 +1,22 @@
-use rusqlite::{Connection, Result};
+use rusqlite::Connection;
 use serde_derive::Deserialize;
 use std::fmt;
 use std::fs;
 use structopt::StructOpt;
-use toml;
 
 mod datetime;
+mod error;
+mod ics;
 mod interface;
+mod parser;
+mod query;
+mod schedule;
 mod sql;
 
 extern crate chrono;
 
+use error::{Error, Result};
+
 #[derive(Debug, Clone)]
 pub struct Task {
     pub id: i32,
@@ -33,7 +39,18 @@ pub struct Log {
     pub date: String,
     pub start: String,
     pub end: String,
+    pub estimate: i32,
     pub review: String,
+    pub sessions: Vec<Session>,
+    /// False when no task has the same name and project
+    pub planned: bool,
+}
+
+/// One start/end pair of work on a logged task
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    pub start: String,
+    pub end: String,
 }
 
 #[derive(Debug)]
@@ -44,13 +61,73 @@ pub struct Note {
     pub notetext: String,
 }
 
-impl fmt::Display for Task {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(
-            f,
-            "- {} ({}) [{}] {}： {}",
-            self.start, self.estimate, self.project, self.name, self.notes
-        )
+#[cfg(test)]
+impl Task {
+    /// An active one-off task in the Work project on 2020-07-01, 30 minutes without a start time
+    pub fn named(name: &str) -> Task {
+        Task {
+            id: 0,
+            status: "ACTIVE".to_string(),
+            name: name.to_string(),
+            notes: "".to_string(),
+            project: "Work".to_string(),
+            start: "".to_string(),
+            estimate: 30,
+            repeat: "".to_string(),
+            next: "2020-07-01".to_string(),
+        }
+    }
+
+    pub fn with_id(mut self, id: i32) -> Task {
+        self.id = id;
+        self
+    }
+
+    pub fn in_project(mut self, project: &str) -> Task {
+        self.project = project.to_string();
+        self
+    }
+
+    pub fn at(mut self, start: &str, estimate: i32) -> Task {
+        self.start = start.to_string();
+        self.estimate = estimate;
+        self
+    }
+
+    pub fn dated(mut self, next: &str) -> Task {
+        self.next = next.to_string();
+        self
+    }
+
+    pub fn repeating(mut self, repeat: &str) -> Task {
+        self.repeat = repeat.to_string();
+        self
+    }
+}
+
+#[cfg(test)]
+impl Log {
+    /// A planned log row on 2020-07-01 in the Work project, spanning its sessions
+    pub fn with_sessions(name: &str, sessions: &[(&str, &str)]) -> Log {
+        Log {
+            id: 0,
+            name: name.to_string(),
+            notes: "".to_string(),
+            project: "Work".to_string(),
+            date: "2020-07-01".to_string(),
+            start: sessions[0].0.to_string(),
+            end: sessions[sessions.len() - 1].1.to_string(),
+            estimate: 0,
+            review: "".to_string(),
+            sessions: sessions
+                .iter()
+                .map(|(start, end)| Session {
+                    start: start.to_string(),
+                    end: end.to_string(),
+                })
+                .collect(),
+            planned: true,
+        }
     }
 }
 
@@ -70,6 +147,16 @@ impl fmt::Display for Note {
 struct Config {
     main_dir: String,
     database_file_name: String,
+    /// Plan line format after the checkbox, see parser::DEFAULT_LINE_TEMPLATE
+    plan_line_template: Option<String>,
+    /// Working hours like "09:00" and "17:30", plans are scheduled when both are set
+    work_start: Option<String>,
+    work_end: Option<String>,
+    /// Breaks inside working hours like "12:00-13:00"
+    #[serde(default)]
+    breaks: Vec<String>,
+    /// Minutes of estimates that fit into a day, defaults to working hours without breaks
+    daily_capacity: Option<i32>,
 }
 
 // Define inputs
@@ -78,24 +165,142 @@ struct Cli {
     /// Config File Path: -c "config.toml"
     #[structopt(short = "c", long = "config_file")]
     config: String,
+
+    /// Print tasks matching a saved filter and exit: -f "Client A this week"
+    #[structopt(short = "f", long = "filter")]
+    filter: Option<String>,
+
+    /// Check a plan/log markdown file for problems and exit: --lint "C:\tasks\20200701.md"
+    #[structopt(long = "lint")]
+    lint: Option<String>,
+
+    /// Keep today's log rows in sync with today's markdown file until stopped,
+    /// checkboxes are applied when the day is over, not on every save
+    #[structopt(long = "watch")]
+    watch: bool,
+
+    /// Write the plan for a date to main_dir and exit: --plan 2020-07-03 or --plan +3d
+    /// Warns when the date is over daily_capacity, moving tasks is only offered in the menu
+    #[structopt(long = "plan")]
+    plan: Option<String>,
+
+    /// Group a new --plan file by project with estimated totals
+    #[structopt(long = "grouped")]
+    grouped: bool,
+
+    /// Import every YYYYMMDD.md in main_dir that has no log rows yet and exit
+    #[structopt(long = "backfill")]
+    backfill: bool,
+
+    /// Print overlapping plan tasks and logged sessions from --from to --to and exit,
+    /// the next 7 days by default
+    #[structopt(long = "conflicts")]
+    conflicts: bool,
+
+    /// Write active tasks with a date and start time to an .ics file in main_dir and exit,
+    /// limited to --from and --to when given
+    #[structopt(long = "export-ics")]
+    export_ics: bool,
+
+    /// Add timed events from an .ics file as fixed-time tasks and exit,
+    /// for --from to --to or the next 7 days: --import-ics "C:\tasks\work.ics"
+    #[structopt(long = "import-ics")]
+    import_ics: Option<String>,
+
+    /// Write the estimate vs actual report for --from to --to, the last 7 days by default, and exit
+    #[structopt(long = "accuracy")]
+    accuracy: bool,
+
+    /// First date for --backfill, --conflicts, --export-ics, --import-ics and --accuracy: --from 2020-06-01
+    #[structopt(long = "from")]
+    from: Option<String>,
+
+    /// Last date for --backfill, --conflicts, --export-ics, --import-ics and --accuracy: --to 2020-06-30
+    #[structopt(long = "to")]
+    to: Option<String>,
 }
 
-fn main() -> Result<()> {
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
     // Read command line arguments
     let args = Cli::from_args();
 
     let filename = args.config.as_str();
-    let contents = fs::read_to_string(filename).expect("Error");
-    let config: Config = toml::from_str(&contents).unwrap();
+    let contents = fs::read_to_string(filename)
+        .map_err(|e| Error::Config(format!("couldn't read {}: {}", filename, e)))?;
+    let config: Config = toml::from_str(&contents)?;
 
     let main_dir = config.main_dir.to_string();
     let database_file_name = config.database_file_name;
     let database_path = main_dir.clone() + &database_file_name;
+    let template = match &config.plan_line_template {
+        Some(template) => parser::LineTemplate::new(template)?,
+        None => parser::LineTemplate::default(),
+    };
+    let schedule = match (&config.work_start, &config.work_end) {
+        (Some(start), Some(end)) => Some(schedule::Schedule::new(start, end, &config.breaks)?),
+        _ => None,
+    };
+    let capacity = config
+        .daily_capacity
+        .or_else(|| schedule.as_ref().map(|s| s.working_minutes()));
 
     let conn = Connection::open(database_path)?;
-    sql::init(&conn).unwrap();
+    sql::init(&conn)?;
 
-    interface::main_menu(&conn, main_dir)?;
+    if let Some(path) = args.lint {
+        interface::lint_markdown_log(&path, &template)?;
+    } else if args.watch {
+        interface::watch_markdown_log(&conn, &main_dir, &template)?;
+    } else if let Some(date) = args.plan {
+        interface::generate_daily_plan(
+            &conn,
+            &main_dir,
+            &date,
+            &template,
+            schedule.as_ref(),
+            capacity,
+            args.grouped,
+        )?;
+    } else if args.conflicts {
+        let from = args.from.unwrap_or_else(|| datetime::yyyymmdd_today_plus_n(0));
+        let to = args.to.unwrap_or_else(|| datetime::yyyymmdd_today_plus_n(6));
+        interface::list_conflicts(&conn, &from, &to)?;
+    } else if args.export_ics {
+        let range = match (args.from, args.to) {
+            (None, None) => None,
+            (Some(from), None) => Some((from.clone(), from)),
+            (None, Some(to)) => Some((datetime::yyyymmdd_today_plus_n(0), to)),
+            (Some(from), Some(to)) => Some((from, to)),
+        };
+        interface::export_calendar(&conn, &main_dir, range)?;
+    } else if let Some(path) = args.import_ics {
+        let from = args.from.unwrap_or_else(|| datetime::yyyymmdd_today_plus_n(0));
+        let to = args.to.unwrap_or_else(|| datetime::yyyymmdd_today_plus_n(6));
+        interface::import_calendar(&conn, &path, &from, &to)?;
+    } else if args.accuracy {
+        let from = args.from.unwrap_or_else(|| datetime::yyyymmdd_today_plus_n(-6));
+        let to = args.to.unwrap_or_else(|| datetime::yyyymmdd_today_plus_n(0));
+        interface::estimate_accuracy_report(&conn, &main_dir, &from, &to)?;
+    } else if args.backfill {
+        interface::backfill_markdown_logs(
+            &conn,
+            &main_dir,
+            &template,
+            args.from.as_deref(),
+            args.to.as_deref(),
+        )?;
+    } else if let Some(name) = args.filter {
+        interface::print_saved_filter(&conn, &name)?;
+    } else {
+        interface::main_menu(&conn, main_dir, &template, schedule.as_ref(), capacity)?;
+    }
 
     Ok(())
 }
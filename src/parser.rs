@@ -0,0 +1,844 @@
+use crate::datetime;
+use crate::error::{Error, Result};
+use crate::{Session, Task};
+
+/// One task line of a plan/log file and the lines indented under it
+#[derive(Debug, Clone, Default)]
+pub struct LogEntry {
+    pub line: usize,
+    /// Some(true) for `- [x]`, Some(false) for `- [ ]`, None without a checkbox
+    pub checked: Option<bool>,
+    pub name: String,
+    pub notes: String,
+    pub project: String,
+    pub planned_start: String,
+    pub estimate: i32,
+    pub sessions: Vec<Session>,
+    pub review: String,
+}
+
+impl LogEntry {
+    /// Start of the first session
+    pub fn start(&self) -> &str {
+        self.sessions.first().map_or("", |s| s.start.as_str())
+    }
+
+    /// End of the last finished session
+    pub fn end(&self) -> &str {
+        self.sessions
+            .iter()
+            .rev()
+            .find(|s| !s.end.is_empty())
+            .map_or("", |s| s.end.as_str())
+    }
+
+    /// Sessions that have both a start and an end time
+    pub fn complete_sessions(&self) -> Vec<Session> {
+        self.sessions
+            .iter()
+            .filter(|s| !s.end.is_empty())
+            .cloned()
+            .collect()
+    }
+
+    /// True when at least one session was finished
+    pub fn is_logged(&self) -> bool {
+        self.sessions.iter().any(|s| !s.end.is_empty())
+    }
+}
+
+/// Entries found in a file, and parse errors for lines that were only partly understood
+#[derive(Debug, Default)]
+pub struct ParsedLog {
+    pub entries: Vec<LogEntry>,
+    pub warnings: Vec<Error>,
+}
+
+impl ParsedLog {
+    fn warn(&mut self, line: usize, message: String) {
+        self.warnings.push(Error::Parse { line, message });
+    }
+}
+
+/// Placeholder that a plan line template can contain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Start,
+    Estimate,
+    Project,
+    Name,
+    Notes,
+}
+
+impl Field {
+    fn from_placeholder(name: &str) -> Option<Field> {
+        match name {
+            "start" => Some(Field::Start),
+            "estimate" => Some(Field::Estimate),
+            "project" => Some(Field::Project),
+            "name" => Some(Field::Name),
+            "notes" => Some(Field::Notes),
+            _ => None,
+        }
+    }
+
+    fn placeholder(&self) -> &'static str {
+        match self {
+            Field::Start => "start",
+            Field::Estimate => "estimate",
+            Field::Project => "project",
+            Field::Name => "name",
+            Field::Notes => "notes",
+        }
+    }
+
+    /// Start time and notes may be left empty in a plan line
+    fn is_required(&self) -> bool {
+        !matches!(self, Field::Start | Field::Notes)
+    }
+}
+
+pub const DEFAULT_LINE_TEMPLATE: &str = "{start} ({estimate}) [{project}] {name}： {notes}";
+
+/// Format of a task line after the `- [ ] ` checkbox, like `{start} ({estimate}) [{project}] {name}： {notes}`
+/// Plans are written with it and logs are read back with it, text between placeholders is matched literally
+#[derive(Debug, Clone)]
+pub struct LineTemplate {
+    prefix: String,
+    /// Each placeholder and the literal text that follows it
+    fields: Vec<(Field, String)>,
+}
+
+impl Default for LineTemplate {
+    fn default() -> LineTemplate {
+        LineTemplate::new(DEFAULT_LINE_TEMPLATE).expect("default line template is valid")
+    }
+}
+
+impl LineTemplate {
+    pub fn new(template: &str) -> Result<LineTemplate> {
+        let mut prefix = String::new();
+        let mut fields: Vec<(Field, String)> = Vec::new();
+        let mut rest = template;
+
+        while let Some(open) = rest.find('{') {
+            let literal = &rest[..open];
+            match fields.last_mut() {
+                Some((_, after)) => after.push_str(literal),
+                None => prefix.push_str(literal),
+            }
+            let close = rest[open..].find('}').ok_or_else(|| {
+                Error::Config(format!("line template '{}' has an unclosed '{{'", template))
+            })? + open;
+            let name = &rest[open + 1..close];
+            let field = Field::from_placeholder(name).ok_or_else(|| {
+                Error::Config(format!("unknown placeholder {{{}}} in line template", name))
+            })?;
+            if fields.iter().any(|(f, _)| *f == field) {
+                return Err(Error::Config(format!(
+                    "placeholder {{{}}} appears twice in line template",
+                    name
+                )));
+            }
+            if let Some((previous, after)) = fields.last() {
+                if after.is_empty() {
+                    return Err(Error::Config(format!(
+                        "{{{}}} and {{{}}} need text between them in line template",
+                        previous.placeholder(),
+                        name
+                    )));
+                }
+            }
+            fields.push((field, String::new()));
+            rest = &rest[close + 1..];
+        }
+        match fields.last_mut() {
+            Some((_, after)) => after.push_str(rest),
+            None => prefix.push_str(rest),
+        }
+
+        if !fields.iter().any(|(f, _)| *f == Field::Name) {
+            return Err(Error::Config("line template needs a {name} placeholder".to_string()));
+        }
+
+        Ok(LineTemplate { prefix, fields })
+    }
+
+    /// Fills in the template, without the checkbox
+    pub fn render(&self, task: &Task) -> String {
+        let mut line = self.prefix.clone();
+        for (field, after) in &self.fields {
+            match field {
+                Field::Start => line.push_str(&task.start),
+                Field::Estimate => line.push_str(&task.estimate.to_string()),
+                Field::Project => line.push_str(&task.project),
+                Field::Name => line.push_str(&task.name),
+                Field::Notes => line.push_str(&task.notes),
+            }
+            line.push_str(after);
+        }
+        line
+    }
+
+    /// Full plan line with an unchecked checkbox
+    pub fn render_line(&self, task: &Task) -> String {
+        format!("- [ ] {}\n", self.render(task))
+    }
+
+    /// Reads the fields of a task line into the entry
+    /// Whitespace around literal text is ignored, so trailing spaces may be trimmed by editors
+    /// When a literal is missing the current field takes the rest of the line
+    fn parse(&self, text: &str, entry: &mut LogEntry) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut rest = text.trim_start();
+
+        let prefix = self.prefix.trim();
+        if !prefix.is_empty() {
+            match rest.strip_prefix(prefix) {
+                Some(r) => rest = r,
+                None => warnings.push(format!("line does not start with '{}'", prefix)),
+            }
+        }
+
+        for (i, (field, after)) in self.fields.iter().enumerate() {
+            let key = match after.trim() {
+                "" if !after.is_empty() => " ",
+                key => key,
+            };
+            let value = if key.is_empty() {
+                std::mem::take(&mut rest)
+            } else {
+                match find_literal(rest, key) {
+                    Some((index, len)) => {
+                        let value = &rest[..index];
+                        rest = &rest[index + len..];
+                        value
+                    }
+                    None => {
+                        let missing = self.fields[i + 1..].iter().find(|(f, _)| f.is_required());
+                        if let Some((next, _)) = missing {
+                            warnings.push(format!(
+                                "missing '{}' before {{{}}}",
+                                key,
+                                next.placeholder()
+                            ));
+                        }
+                        self.set_field(*field, rest.trim(), entry, &mut warnings);
+                        break;
+                    }
+                }
+            };
+            self.set_field(*field, value.trim(), entry, &mut warnings);
+        }
+
+        warnings
+    }
+
+    fn set_field(&self, field: Field, value: &str, entry: &mut LogEntry, warnings: &mut Vec<String>) {
+        if value.is_empty() && field.is_required() {
+            warnings.push(format!("missing {{{}}}", field.placeholder()));
+            return;
+        }
+        match field {
+            Field::Start => entry.planned_start = value.to_string(),
+            Field::Estimate => match value.parse::<i32>() {
+                Ok(minutes) => entry.estimate = minutes,
+                Err(_) => warnings.push(format!("estimate '{}' is not a number of minutes", value)),
+            },
+            Field::Project => entry.project = value.to_string(),
+            Field::Name => entry.name = value.to_string(),
+            Field::Notes => entry.notes = value.to_string(),
+        }
+    }
+}
+
+/// Finds literal template text, a fullwidth colon also matches an ASCII colon
+/// followed by a space or at the end of the line
+fn find_literal(rest: &str, key: &str) -> Option<(usize, usize)> {
+    if let Some(index) = rest.find(key) {
+        return Some((index, key.len()));
+    }
+    if !key.contains('：') {
+        return None;
+    }
+    let ascii = key.replace('：', ": ");
+    rest.find(&ascii).map(|index| (index, ascii.len())).or_else(|| {
+        let ascii = key.replace('：', ":");
+        rest.trim_end()
+            .strip_suffix(&ascii)
+            .map(|r| (r.len(), ascii.len()))
+    })
+}
+
+/// Parses a plan/log file written as `- [ ] ` followed by a line in the template's format,
+/// then indented `  - HH:MM` time lines and `  - review` lines, times may also be 9:30pm or HH:MM:SS
+/// Times alternate between start and end, so interrupted work is several sessions
+pub fn parse_log(text: &str, template: &LineTemplate) -> ParsedLog {
+    let mut parsed = ParsedLog::default();
+
+    for (i, l) in text.lines().enumerate() {
+        let line_number = i + 1;
+        if let Some(rest) = l.strip_prefix("- ") {
+            let entry = parse_task_line(rest, line_number, template, &mut parsed);
+            parsed.entries.push(entry);
+        } else if let Some(rest) = l.strip_prefix("  -") {
+            parse_indented_line(rest.trim(), line_number, &mut parsed);
+        } else if l.trim_start().starts_with('-') {
+            parsed.warn(line_number, "list item is not indented by two spaces".to_string());
+        }
+    }
+
+    let unfinished: Vec<(usize, String)> = parsed
+        .entries
+        .iter()
+        .filter(|entry| entry.sessions.iter().any(|s| s.end.is_empty()))
+        .map(|entry| (entry.line, format!("{} has a start time but no end time", entry.name)))
+        .collect();
+    for (line, message) in unfinished {
+        parsed.warn(line, message);
+    }
+    parsed.warnings.sort_by_key(|w| w.line());
+
+    parsed
+}
+
+/// Heading of the section listing overlapping tasks, it is rewritten on every merge
+pub const CONFLICTS_HEADING: &str = "## Conflicts";
+
+/// Updates an existing plan file with freshly generated tasks
+/// Task lines matching a task by name and project are rewritten keeping their checkbox,
+/// new tasks are inserted before the first later task, and every other line is kept,
+/// including time entries, reviews and tasks added by hand
+/// In a grouped plan new tasks go into their project's section, or Routines for routines,
+/// a missing section is added at the end, and the totals in the headings are recounted
+pub fn merge_plan(existing: &str, tasks: &[Task], template: &LineTemplate) -> String {
+    let parsed = parse_log(existing, template);
+    let lines: Vec<&str> = existing.lines().collect();
+    let mut used = vec![false; tasks.len()];
+    let mut replaced: Vec<(usize, String)> = Vec::new();
+
+    for entry in &parsed.entries {
+        let matching = tasks
+            .iter()
+            .enumerate()
+            .position(|(i, t)| !used[i] && t.name == entry.name && t.project == entry.project);
+        if let Some(i) = matching {
+            used[i] = true;
+            let checkbox = if entry.checked == Some(true) { "[x]" } else { "[ ]" };
+            replaced.push((entry.line, format!("- {} {}", checkbox, template.render(&tasks[i]))));
+        }
+    }
+
+    // Sections of a grouped plan as (heading line, first line after the section, name)
+    let sections: Vec<(usize, usize, &str)> = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, l)| {
+            let name = section_heading(l)?;
+            let end = lines
+                .iter()
+                .enumerate()
+                .skip(i + 1)
+                .find(|(_, l)| l.starts_with('#'))
+                .map_or(lines.len() + 1, |(j, _)| j + 1);
+            Some((i + 1, end, name))
+        })
+        .collect();
+    let grouped = !sections.is_empty();
+
+    // New tasks go before the first entry that starts later, or after everything else
+    // Starts are compared as times, so 9:30 is before 10:00, unreadable starts are never later
+    let mut inserted: Vec<(usize, String)> = Vec::new();
+    let mut appended: Vec<String> = Vec::new();
+    let mut new_sections: Vec<(String, Vec<String>)> = Vec::new();
+    for (task, _) in tasks.iter().zip(&used).filter(|(_, used)| !**used) {
+        let line = template.render_line(task).trim_end_matches('\n').to_string();
+        let section_name = if task.repeat.is_empty() { task.project.as_str() } else { "Routines" };
+        let section = sections.iter().find(|(_, _, name)| *name == section_name);
+        if grouped && section.is_none() {
+            match new_sections.iter_mut().find(|(name, _)| name == section_name) {
+                Some((_, section_lines)) => section_lines.push(line),
+                None => new_sections.push((section_name.to_string(), vec![line])),
+            }
+            continue;
+        }
+
+        let start = datetime::parse_time(&task.start).ok();
+        let later = parsed
+            .entries
+            .iter()
+            .filter(|e| match section {
+                Some((from, to, _)) => e.line > *from && e.line < *to,
+                None => true,
+            })
+            .find(|e| match (start, datetime::parse_time(&e.planned_start).ok()) {
+                (Some(start), Some(planned)) => planned > start,
+                _ => false,
+            });
+        match (later, section) {
+            (Some(entry), _) => inserted.push((entry.line, line)),
+            // After the last non-blank line of the section
+            (None, Some((from, to, _))) if *to <= lines.len() => {
+                let mut at = *to;
+                while at - 1 > *from && lines[at - 2].trim().is_empty() {
+                    at -= 1;
+                }
+                inserted.push((at, line));
+            }
+            (None, _) => appended.push(line),
+        }
+    }
+
+    let mut output_string = String::new();
+    let mut in_conflicts = false;
+    for (i, l) in lines.iter().enumerate() {
+        let line_number = i + 1;
+        if l.trim() == CONFLICTS_HEADING {
+            in_conflicts = true;
+            continue;
+        }
+        if in_conflicts {
+            if !l.starts_with('#') {
+                continue;
+            }
+            in_conflicts = false;
+        }
+        for (_, line) in inserted.iter().filter(|(at, _)| *at == line_number) {
+            output_string.push_str(line);
+            output_string.push('\n');
+        }
+        match replaced.iter().find(|(at, _)| *at == line_number) {
+            Some((_, line)) => output_string.push_str(line),
+            None => output_string.push_str(l),
+        }
+        output_string.push('\n');
+    }
+
+    while output_string.ends_with("\n\n") {
+        output_string.pop();
+    }
+    for line in appended {
+        output_string.push_str(&line);
+        output_string.push('\n');
+    }
+
+    if !grouped {
+        return output_string;
+    }
+    for (name, section_lines) in new_sections {
+        output_string.push_str(&format!("\n## {} (0 min)\n", name));
+        for line in section_lines {
+            output_string.push_str(&line);
+            output_string.push('\n');
+        }
+    }
+    recount_plan_totals(&output_string, template)
+}
+
+/// Project of a grouped plan heading like "## Work (90 min)"
+fn section_heading(line: &str) -> Option<&str> {
+    let (name, total) = line.strip_prefix("## ")?.rsplit_once(" (")?;
+    total.strip_suffix(" min)")?.parse::<i32>().ok()?;
+    Some(name)
+}
+
+/// Date of a grouped plan heading like "# 2020-07-01 (420 min planned)"
+fn day_heading(line: &str) -> Option<&str> {
+    let (date, total) = line.strip_prefix("# ")?.rsplit_once(" (")?;
+    total.strip_suffix(" min planned)")?.parse::<i32>().ok()?;
+    Some(date)
+}
+
+/// Rewrites the totals of a grouped plan's headings from the estimates of its task lines
+fn recount_plan_totals(plan: &str, template: &LineTemplate) -> String {
+    let entries = parse_log(plan, template).entries;
+    let lines: Vec<&str> = plan.lines().collect();
+    let minutes_between = |from: usize, to: usize| -> i32 {
+        entries
+            .iter()
+            .filter(|e| e.line > from && e.line < to)
+            .map(|e| e.estimate)
+            .sum()
+    };
+
+    let mut output_string = String::new();
+    for (i, l) in lines.iter().enumerate() {
+        if let Some(name) = section_heading(l) {
+            let end = lines
+                .iter()
+                .enumerate()
+                .skip(i + 1)
+                .find(|(_, l)| l.starts_with('#'))
+                .map_or(lines.len() + 1, |(j, _)| j + 1);
+            let minutes = minutes_between(i + 1, end);
+            output_string.push_str(&format!("## {} ({} min)", name, minutes));
+        } else if let Some(date) = day_heading(l) {
+            let minutes = minutes_between(0, lines.len() + 1);
+            output_string.push_str(&format!("# {} ({} min planned)", date, minutes));
+        } else {
+            output_string.push_str(l);
+        }
+        output_string.push('\n');
+    }
+
+    output_string
+}
+
+fn parse_task_line(
+    rest: &str,
+    line_number: usize,
+    template: &LineTemplate,
+    parsed: &mut ParsedLog,
+) -> LogEntry {
+    let mut entry = LogEntry {
+        line: line_number,
+        ..Default::default()
+    };
+    let mut rest = rest.trim_start();
+
+    if let Some(r) = rest.strip_prefix("[ ]") {
+        entry.checked = Some(false);
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix("[x]").or_else(|| rest.strip_prefix("[X]")) {
+        entry.checked = Some(true);
+        rest = r;
+    }
+
+    for message in template.parse(rest, &mut entry) {
+        parsed.warn(line_number, message);
+    }
+
+    entry
+}
+
+fn parse_indented_line(text: &str, line_number: usize, parsed: &mut ParsedLog) {
+    let entry = match parsed.entries.last_mut() {
+        Some(entry) => entry,
+        None => {
+            parsed.warn(line_number, "indented line before any task".to_string());
+            return;
+        }
+    };
+
+    let mut warning = None;
+    if looks_like_time(text) {
+        match datetime::parse_time(text) {
+            Err(e) => warning = Some(e.to_string()),
+            Ok(time) => {
+                let time = datetime::format_time(time);
+                match entry.sessions.last_mut() {
+                    Some(session) if session.end.is_empty() => session.end = time,
+                    _ => entry.sessions.push(Session {
+                        start: time,
+                        end: "".to_string(),
+                    }),
+                }
+            }
+        }
+    } else if entry.review.is_empty() {
+        entry.review = text.to_string();
+    } else {
+        entry.review = format!("{}; {}", entry.review, text);
+    }
+
+    if let Some(message) = warning {
+        parsed.warn(line_number, message);
+    }
+}
+
+/// Digits separated by colons like 9:30 or 09:30:00, optionally followed by am/pm,
+/// or an hour followed by am/pm like 9pm
+fn looks_like_time(text: &str) -> bool {
+    let lower = text.to_lowercase();
+    let trimmed = lower.trim_end_matches("am").trim_end_matches("pm").trim_end();
+    let twelve_hour = trimmed.len() < lower.len();
+
+    let parts: Vec<&str> = trimmed.split(':').collect();
+    let all_numbers = parts
+        .iter()
+        .all(|p| !p.is_empty() && p.len() <= 2 && p.chars().all(|c| c.is_ascii_digit()));
+
+    all_numbers && (parts.len() == 2 || parts.len() == 3 || twelve_hour && parts.len() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> ParsedLog {
+        parse_log(text, &LineTemplate::default())
+    }
+
+    #[test]
+    fn default_template_reads_all_fields() {
+        let parsed = parse("- [ ] 09:00 (30) [Work] Email [urgent]： inbox zero\n");
+
+        assert!(parsed.warnings.is_empty());
+        let entry = &parsed.entries[0];
+        assert_eq!(entry.line, 1);
+        assert_eq!(entry.planned_start, "09:00");
+        assert_eq!(entry.estimate, 30);
+        assert_eq!(entry.project, "Work");
+        assert_eq!(entry.name, "Email [urgent]");
+        assert_eq!(entry.notes, "inbox zero");
+    }
+
+    #[test]
+    fn default_template_accepts_ascii_colon_and_empty_start() {
+        let parsed = parse("- [ ]  (15) [Home] Dishes:\n- (10) [Home] Laundry: whites\n");
+
+        assert!(parsed.warnings.is_empty());
+        assert_eq!(parsed.entries[0].planned_start, "");
+        assert_eq!(parsed.entries[0].name, "Dishes");
+        assert_eq!(parsed.entries[0].notes, "");
+        assert_eq!(parsed.entries[1].name, "Laundry");
+        assert_eq!(parsed.entries[1].notes, "whites");
+    }
+
+    #[test]
+    fn custom_template_round_trips() {
+        let template = LineTemplate::new("{name} | {project} | {start} | {estimate} min").unwrap();
+        let task = Task::named("Email").at("09:00", 30);
+
+        let line = template.render_line(&task);
+        assert_eq!(line, "- [ ] Email | Work | 09:00 | 30 min\n");
+
+        let parsed = parse_log(&line, &template);
+        assert!(parsed.warnings.is_empty());
+        let entry = &parsed.entries[0];
+        assert_eq!(entry.name, "Email");
+        assert_eq!(entry.project, "Work");
+        assert_eq!(entry.planned_start, "09:00");
+        assert_eq!(entry.estimate, 30);
+    }
+
+    #[test]
+    fn invalid_templates_are_config_errors() {
+        assert!(LineTemplate::new("{name}{project}").is_err());
+        assert!(LineTemplate::new("{name} {priority}").is_err());
+        assert!(LineTemplate::new("{name} {name}").is_err());
+        assert!(LineTemplate::new("{start} ({estimate})").is_err());
+        assert!(LineTemplate::new("{name").is_err());
+    }
+
+    #[test]
+    fn malformed_lines_produce_warnings_with_line_numbers() {
+        let text = "- [ ] 09:00 (x) [Work] Bad estimate：\n\
+                    - [ ] (15) Read: chapter 2\n\
+                    \x20- 10:00\n\
+                    \x20\x20- 25:00\n";
+        let parsed = parse(text);
+
+        let lines: Vec<usize> = parsed.warnings.iter().map(|w| w.line()).collect();
+        assert_eq!(lines, vec![1, 2, 2, 3, 4]);
+        assert!(parsed.warnings[0].to_string().contains("estimate 'x'"));
+        assert!(parsed.warnings[1].to_string().contains("missing ') ['"));
+        assert!(parsed.warnings[3]
+            .to_string()
+            .contains("not indented by two spaces"));
+        assert!(parsed.warnings[4].to_string().contains("not a valid time"));
+    }
+
+    #[test]
+    fn indented_line_before_any_task_is_a_warning() {
+        let parsed = parse("  - 09:00\n");
+
+        assert!(parsed.entries.is_empty());
+        assert_eq!(parsed.warnings.len(), 1);
+        assert_eq!(parsed.warnings[0].line(), 1);
+    }
+
+    #[test]
+    fn times_alternate_into_sessions_and_reviews_are_joined() {
+        let text = "- [x] 09:00 (60) [Work] Report：\n\
+                    \x20\x20- 9:05\n\
+                    \x20\x20- 09:40\n\
+                    \x20\x20- got interrupted\n\
+                    \x20\x20- 1:15pm\n\
+                    \x20\x20- 13:30:30\n\
+                    \x20\x20- finished\n";
+        let parsed = parse(text);
+
+        assert!(parsed.warnings.is_empty());
+        let entry = &parsed.entries[0];
+        assert_eq!(entry.sessions.len(), 2);
+        assert_eq!(entry.sessions[0].start, "09:05");
+        assert_eq!(entry.sessions[0].end, "09:40");
+        assert_eq!(entry.sessions[1].start, "13:15");
+        assert_eq!(entry.sessions[1].end, "13:30:30");
+        assert_eq!(entry.start(), "09:05");
+        assert_eq!(entry.end(), "13:30:30");
+        assert_eq!(entry.review, "got interrupted; finished");
+        assert!(entry.is_logged());
+    }
+
+    #[test]
+    fn unfinished_session_is_a_warning_and_not_complete() {
+        let text = "- [ ] 09:00 (60) [Work] Report：\n\
+                    \x20\x20- 09:00\n\
+                    \x20\x20- 09:30\n\
+                    \x20\x20- 10:00\n";
+        let parsed = parse(text);
+
+        assert_eq!(parsed.warnings.len(), 1);
+        assert_eq!(parsed.warnings[0].line(), 1);
+        assert!(parsed.warnings[0].to_string().contains("no end time"));
+        let entry = &parsed.entries[0];
+        assert_eq!(entry.sessions.len(), 2);
+        assert_eq!(entry.complete_sessions().len(), 1);
+        assert_eq!(entry.end(), "09:30");
+    }
+
+    #[test]
+    fn entry_without_times_is_not_logged() {
+        let parsed = parse("- [ ] 09:00 (60) [Work] Report：\n");
+
+        assert!(!parsed.entries[0].is_logged());
+        assert_eq!(parsed.entries[0].start(), "");
+    }
+
+    #[test]
+    fn checkbox_states() {
+        let text = "- [ ] 09:00 (5) [A] Open：\n\
+                    - [x] 09:00 (5) [A] Done：\n\
+                    - [X] 09:00 (5) [A] Also done：\n\
+                    - 09:00 (5) [A] Legacy：\n";
+        let parsed = parse(text);
+
+        assert!(parsed.warnings.is_empty());
+        let checked: Vec<Option<bool>> = parsed.entries.iter().map(|e| e.checked).collect();
+        assert_eq!(checked, vec![Some(false), Some(true), Some(true), None]);
+        assert_eq!(parsed.entries[3].name, "Legacy");
+    }
+
+    #[test]
+    fn merge_keeps_ticks_time_entries_and_lines_added_by_hand() {
+        let existing = "- [x] 09:00 (30) [Work] Email： old notes\n\
+                        \x20\x20- 09:00\n\
+                        \x20\x20- 09:25\n\
+                        \x20\x20- done early\n\
+                        - [ ] 10:00 (15) [Home] Call mum：\n\
+                        Remember to buy milk\n";
+        let tasks = vec![Task::named("Email").at("09:00", 45)];
+
+        let merged = merge_plan(existing, &tasks, &LineTemplate::default());
+
+        assert_eq!(
+            merged,
+            "- [x] 09:00 (45) [Work] Email： \n\
+             \x20\x20- 09:00\n\
+             \x20\x20- 09:25\n\
+             \x20\x20- done early\n\
+             - [ ] 10:00 (15) [Home] Call mum：\n\
+             Remember to buy milk\n"
+        );
+    }
+
+    #[test]
+    fn merge_inserts_new_tasks_by_start_time() {
+        let existing = "- [ ] 9:30 (30) [Work] Early：\n- [ ] 13:00 (30) [Work] Late：\n";
+        let tasks = vec![
+            Task::named("Early").at("9:30", 30),
+            Task::named("Middle").at("10:00", 30),
+            Task::named("Late").at("13:00", 30),
+            Task::named("Flexible"),
+        ];
+
+        let merged = merge_plan(existing, &tasks, &LineTemplate::default());
+
+        assert_eq!(
+            merged,
+            "- [ ] 9:30 (30) [Work] Early： \n\
+             - [ ] 10:00 (30) [Work] Middle： \n\
+             - [ ] 13:00 (30) [Work] Late： \n\
+             - [ ]  (30) [Work] Flexible： \n"
+        );
+    }
+
+    #[test]
+    fn merge_drops_the_old_conflicts_section() {
+        let existing = "- [ ] 09:00 (30) [Work] Email：\n\
+                        \n\
+                        ## Conflicts\n\
+                        09:00 Email (30 min) overlaps 09:10 Call (20 min)\n\
+                        \n\
+                        ## Notes\n\
+                        keep this\n";
+        let tasks = vec![Task::named("Email").at("09:00", 30)];
+
+        let merged = merge_plan(existing, &tasks, &LineTemplate::default());
+
+        assert_eq!(
+            merged,
+            "- [ ] 09:00 (30) [Work] Email： \n\n## Notes\nkeep this\n"
+        );
+    }
+
+    #[test]
+    fn merge_into_grouped_plan_uses_sections_and_recounts_totals() {
+        let existing = "# 2020-07-01 (45 min planned)\n\
+                        \n\
+                        ## Routines (15 min)\n\
+                        - [ ] 08:30 (15) [Home] Stretch：\n\
+                        \n\
+                        ## Work (30 min)\n\
+                        - [x] 09:00 (30) [Work] Email：\n\
+                        \x20\x20- 09:00\n\
+                        \x20\x20- 09:30\n\
+                        \n\
+                        ## Conflicts\n\
+                        08:30 Stretch (15 min) overlaps 08:40 Call (10 min)\n";
+        let mut stretch = Task::named("Stretch").in_project("Home").at("08:30", 15);
+        stretch.repeat = "+1d".to_string();
+        let tasks = vec![
+            stretch,
+            Task::named("Standup").at("08:45", 15),
+            Task::named("Email").at("09:00", 30),
+            Task::named("Report").at("10:00", 60),
+            Task::named("Groceries").in_project("Home").at("", 20),
+        ];
+
+        let merged = merge_plan(existing, &tasks, &LineTemplate::default());
+
+        assert_eq!(
+            merged,
+            "# 2020-07-01 (140 min planned)\n\
+             \n\
+             ## Routines (15 min)\n\
+             - [ ] 08:30 (15) [Home] Stretch： \n\
+             \n\
+             ## Work (105 min)\n\
+             - [ ] 08:45 (15) [Work] Standup： \n\
+             - [x] 09:00 (30) [Work] Email： \n\
+             \x20\x20- 09:00\n\
+             \x20\x20- 09:30\n\
+             - [ ] 10:00 (60) [Work] Report： \n\
+             \n\
+             ## Home (20 min)\n\
+             - [ ]  (20) [Home] Groceries： \n"
+        );
+    }
+
+    #[test]
+    fn merge_into_last_grouped_section_appends_to_it() {
+        let existing = "# 2020-07-01 (30 min planned)\n\
+                        \n\
+                        ## Work (30 min)\n\
+                        - [ ] 09:00 (30) [Work] Email：\n";
+        let tasks = vec![
+            Task::named("Email").at("09:00", 30),
+            Task::named("Report").at("", 60),
+        ];
+
+        let merged = merge_plan(existing, &tasks, &LineTemplate::default());
+
+        assert_eq!(
+            merged,
+            "# 2020-07-01 (90 min planned)\n\
+             \n\
+             ## Work (90 min)\n\
+             - [ ] 09:00 (30) [Work] Email： \n\
+             - [ ]  (60) [Work] Report： \n"
+        );
+    }
+}
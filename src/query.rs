@@ -0,0 +1,320 @@
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{Connection, Result};
+
+use crate::datetime;
+use crate::Task;
+
+/// Which repeat strings a query should match
+#[derive(Debug, Clone, PartialEq)]
+pub enum RepeatFilter {
+    /// Routines, any task with a repeat string
+    Routine,
+    /// One-off tasks without a repeat string
+    OneOff,
+    /// Tasks with exactly the given repeat string
+    Exactly(String),
+}
+
+/// Composable filter for the tasks table
+/// Every condition that is set is combined with AND, and all values are bound as parameters
+#[derive(Debug, Clone, Default)]
+pub struct TaskQuery {
+    ids: Option<Vec<i32>>,
+    name: Option<String>,
+    status: Option<String>,
+    project: Option<String>,
+    tags: Vec<String>,
+    next_from: Option<String>,
+    next_to: Option<String>,
+    repeat: Option<RepeatFilter>,
+    text: Option<String>,
+    notes_as_of: Option<String>,
+    movable: bool,
+}
+
+impl TaskQuery {
+    pub fn new() -> TaskQuery {
+        TaskQuery::default()
+    }
+
+    pub fn ids(mut self, ids: &[i32]) -> TaskQuery {
+        self.ids = Some(ids.to_vec());
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> TaskQuery {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn status(mut self, status: &str) -> TaskQuery {
+        self.status = Some(status.to_string());
+        self
+    }
+
+    pub fn project(mut self, project: &str) -> TaskQuery {
+        self.project = Some(project.to_string());
+        self
+    }
+
+    /// Tasks must carry every given tag
+    pub fn tag(mut self, tag: &str) -> TaskQuery {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    /// Tasks whose next date is on the given date
+    pub fn on(self, date: &str) -> TaskQuery {
+        self.next_from(date).next_to(date)
+    }
+
+    /// Tasks whose next date is on or after the given date
+    pub fn next_from(mut self, date: &str) -> TaskQuery {
+        self.next_from = Some(date.to_string());
+        self
+    }
+
+    /// Tasks whose next date is on or before the given date
+    pub fn next_to(mut self, date: &str) -> TaskQuery {
+        self.next_to = Some(date.to_string());
+        self
+    }
+
+    pub fn repeat(mut self, repeat: RepeatFilter) -> TaskQuery {
+        self.repeat = Some(repeat);
+        self
+    }
+
+    /// Case insensitive search in task names and notes
+    pub fn text(mut self, text: &str) -> TaskQuery {
+        self.text = Some(text.to_string());
+        self
+    }
+
+    /// Leaves out tasks imported from a calendar, they are fixed appointments
+    /// that must not be moved to another date
+    pub fn movable(mut self) -> TaskQuery {
+        self.movable = true;
+        self
+    }
+
+    /// Fills in the latest note started on or before the given date
+    pub fn notes_as_of(mut self, date: &str) -> TaskQuery {
+        self.notes_as_of = Some(date.to_string());
+        self
+    }
+
+    /// Builds the SQL string and the values bound to its parameters
+    fn to_sql(&self) -> (String, Vec<SqlValue>) {
+        let mut values: Vec<SqlValue> = Vec::new();
+        let mut conditions: Vec<String> = Vec::new();
+
+        let (notes_column, notes_join) = match &self.notes_as_of {
+            Some(date) => {
+                values.push(SqlValue::from(date.clone()));
+                (
+                    "ifnull(n.notetext, '')",
+                    "LEFT OUTER JOIN (
+                        SELECT id, MAX(start), notetext
+                        FROM (
+                            SELECT id, start, notetext
+                            FROM note
+                            WHERE date(start) <= ?
+                            ORDER BY id)
+                        GROUP BY id
+                    ) as n
+                    ON t.id = n.id",
+                )
+            }
+            None => ("''", ""),
+        };
+
+        if let Some(ids) = &self.ids {
+            let placeholders = vec!["?"; ids.len()].join(", ");
+            conditions.push(format!("t.id IN ({})", placeholders));
+            values.extend(ids.iter().map(|id| SqlValue::from(*id)));
+        }
+        if let Some(name) = &self.name {
+            conditions.push("t.name = ?".to_string());
+            values.push(SqlValue::from(name.clone()));
+        }
+        if let Some(status) = &self.status {
+            conditions.push("t.status = ?".to_string());
+            values.push(SqlValue::from(status.clone()));
+        }
+        if let Some(project) = &self.project {
+            conditions.push("t.project = ?".to_string());
+            values.push(SqlValue::from(project.clone()));
+        }
+        for tag in &self.tags {
+            conditions.push(
+                "EXISTS (SELECT 1 FROM tags WHERE tags.id = t.id AND tags.tag = ?)".to_string(),
+            );
+            values.push(SqlValue::from(tag.clone()));
+        }
+        if let Some(from) = &self.next_from {
+            conditions.push("t.next >= ?".to_string());
+            values.push(SqlValue::from(from.clone()));
+        }
+        if let Some(to) = &self.next_to {
+            conditions.push("t.next <= ?".to_string());
+            values.push(SqlValue::from(to.clone()));
+        }
+        match &self.repeat {
+            Some(RepeatFilter::Routine) => conditions.push("t.repeat <> ''".to_string()),
+            Some(RepeatFilter::OneOff) => conditions.push("t.repeat = ''".to_string()),
+            Some(RepeatFilter::Exactly(repeat)) => {
+                conditions.push("t.repeat = ?".to_string());
+                values.push(SqlValue::from(repeat.clone()));
+            }
+            None => (),
+        }
+        if self.movable {
+            conditions.push(
+                "NOT EXISTS (SELECT 1 FROM calendar_events WHERE calendar_events.task_id = t.id)"
+                    .to_string(),
+            );
+        }
+        if let Some(text) = &self.text {
+            let pattern = format!("%{}%", escape_like(text));
+            conditions.push(
+                "(t.name LIKE ? ESCAPE '\\' OR EXISTS (SELECT 1 FROM note
+                    WHERE note.id = t.id AND note.notetext LIKE ? ESCAPE '\\'))"
+                    .to_string(),
+            );
+            values.push(SqlValue::from(pattern.clone()));
+            values.push(SqlValue::from(pattern));
+        }
+
+        let where_clause = if conditions.is_empty() {
+            "".to_string()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        let query = format!(
+            "SELECT t.id, t.name, t.project, t.start, t.estimate,
+            t.repeat, t.next, {}, t.status
+            FROM tasks as t
+            {}
+            {}
+            ORDER BY t.start",
+            notes_column, notes_join, where_clause
+        );
+
+        (query, values)
+    }
+
+    /// Runs the query and returns matching tasks ordered by start time
+    pub fn run(&self, conn: &Connection) -> Result<Vec<Task>> {
+        let (query, values) = self.to_sql();
+        let mut stmt = conn.prepare(&query)?;
+
+        let task_iter = stmt.query_map(values, |row| {
+            Ok(Task {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                project: row.get(2)?,
+                start: row.get(3)?,
+                estimate: row.get(4)?,
+                repeat: row.get(5)?,
+                next: row.get(6)?,
+                notes: row.get(7)?,
+                status: row.get(8)?,
+            })
+        })?;
+
+        let mut vec = Vec::new();
+        for task in task_iter {
+            vec.push(task?);
+        }
+
+        Ok(vec)
+    }
+}
+
+/// Escapes the LIKE wildcards % and _ so they match themselves, used with ESCAPE '\\'
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Filter criteria as entered by the user, so they can be saved and run again later
+/// Empty fields are ignored, dates may be relative to today like "+7d"
+#[derive(Debug, Clone, Default)]
+pub struct SavedFilter {
+    pub name: String,
+    pub status: String,
+    pub project: String,
+    pub tags: String,
+    pub next_from: String,
+    pub next_to: String,
+    pub repeat: String,
+    pub text: String,
+}
+
+impl SavedFilter {
+    /// Builds a TaskQuery, resolving relative dates against today
+    pub fn to_query(&self) -> TaskQuery {
+        let mut query = TaskQuery::new();
+
+        if !self.status.is_empty() {
+            query = query.status(&self.status);
+        }
+        if !self.project.is_empty() {
+            query = query.project(&self.project);
+        }
+        for tag in self.tags.split_whitespace() {
+            query = query.tag(tag);
+        }
+        if !self.next_from.is_empty() {
+            query = query.next_from(&datetime::resolve_date(&self.next_from));
+        }
+        if !self.next_to.is_empty() {
+            query = query.next_to(&datetime::resolve_date(&self.next_to));
+        }
+        match self.repeat.as_str() {
+            "" => (),
+            "routine" => query = query.repeat(RepeatFilter::Routine),
+            "none" => query = query.repeat(RepeatFilter::OneOff),
+            r => query = query.repeat(RepeatFilter::Exactly(r.to_string())),
+        }
+        if !self.text.is_empty() {
+            query = query.text(&self.text);
+        }
+
+        query
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql;
+
+    fn names(conn: &Connection, text: &str) -> Vec<String> {
+        let tasks = TaskQuery::new().text(text).run(conn).unwrap();
+        tasks.into_iter().map(|t| t.name).collect()
+    }
+
+    #[test]
+    fn text_search_matches_wildcards_literally() {
+        let conn = Connection::open_in_memory().unwrap();
+        sql::init(&conn).unwrap();
+        for name in &[
+            "Raise to 50%",
+            "Raise to 500",
+            "a_b report",
+            "axb report",
+            "C:\\tasks",
+        ] {
+            sql::add_task(&conn, Task::named(name)).unwrap();
+        }
+
+        assert_eq!(names(&conn, "50%"), vec!["Raise to 50%"]);
+        assert_eq!(names(&conn, "a_b"), vec!["a_b report"]);
+        assert_eq!(names(&conn, "C:\\t"), vec!["C:\\tasks"]);
+        assert_eq!(names(&conn, "raise").len(), 2);
+    }
+}
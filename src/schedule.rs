@@ -0,0 +1,340 @@
+use chrono::{NaiveTime, Timelike};
+
+use crate::datetime;
+use crate::error::{Error, Result};
+use crate::{Log, Task};
+
+/// Working hours and breaks that flexible tasks are fitted into
+/// Times are kept as minutes after midnight
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    work_start: i64,
+    work_end: i64,
+    breaks: Vec<(i64, i64)>,
+}
+
+impl Schedule {
+    /// Builds a schedule from config values like "09:00", "17:30" and ["12:00-13:00"]
+    pub fn new(work_start: &str, work_end: &str, breaks: &[String]) -> Result<Schedule> {
+        let work_start = config_minutes(work_start)?;
+        let work_end = config_minutes(work_end)?;
+        if work_end <= work_start {
+            return Err(Error::Config("work_end must be after work_start".to_string()));
+        }
+
+        let mut parsed_breaks = Vec::new();
+        for b in breaks {
+            let (start, end) = b.split_once('-').ok_or_else(|| {
+                Error::Config(format!("break '{}' should look like 12:00-13:00", b))
+            })?;
+            let (start, end) = (config_minutes(start)?, config_minutes(end)?);
+            if end <= start {
+                return Err(Error::Config(format!("break '{}' ends before it starts", b)));
+            }
+            parsed_breaks.push((start, end));
+        }
+
+        Ok(Schedule {
+            work_start,
+            work_end,
+            breaks: parsed_breaks,
+        })
+    }
+
+    /// Minutes of working hours that are not breaks
+    pub fn working_minutes(&self) -> i32 {
+        let breaks: i64 = self
+            .breaks
+            .iter()
+            .map(|(start, end)| (*end).min(self.work_end) - (*start).max(self.work_start))
+            .filter(|minutes| *minutes > 0)
+            .sum();
+        (self.work_end - self.work_start - breaks) as i32
+    }
+
+    /// Gives every task without a start time the earliest free slot that fits its estimate
+    /// Tasks with a start time stay where they are and block their estimate
+    /// Returns the tasks ordered by start and the flexible tasks that didn't fit, which keep an empty start
+    pub fn assign(&self, tasks: Vec<Task>) -> (Vec<Task>, Vec<Task>) {
+        let mut busy = self.breaks.clone();
+        let (fixed, flexible): (Vec<Task>, Vec<Task>) =
+            tasks.into_iter().partition(|t| !t.start.trim().is_empty());
+
+        for task in &fixed {
+            if let Ok(time) = datetime::parse_time(&task.start) {
+                let start = minutes_of(time);
+                busy.push((start, start + i64::from(task.estimate.max(0))));
+            }
+        }
+
+        let mut scheduled = fixed;
+        let mut unscheduled = Vec::new();
+        for mut task in flexible {
+            let length = i64::from(task.estimate.max(0));
+            match self.first_free_slot(&busy, length) {
+                Some(start) => {
+                    busy.push((start, start + length));
+                    task.start = format_minutes(start);
+                    scheduled.push(task);
+                }
+                None => unscheduled.push(task),
+            }
+        }
+
+        scheduled.sort_by_key(|t| datetime::parse_time(&t.start).map(minutes_of).unwrap_or(0));
+        scheduled.extend(unscheduled.iter().cloned());
+        (scheduled, unscheduled)
+    }
+
+    fn first_free_slot(&self, busy: &[(i64, i64)], length: i64) -> Option<i64> {
+        let mut candidate = self.work_start;
+        while let Some((_, end)) = busy
+            .iter()
+            .find(|(start, end)| *start < candidate + length && candidate < *end)
+        {
+            candidate = *end;
+        }
+        if candidate + length <= self.work_end {
+            Some(candidate)
+        } else {
+            None
+        }
+    }
+}
+
+/// Pairs of tasks whose start + estimate windows overlap, as indexes into tasks
+/// Tasks without a readable start time are ignored
+pub fn task_conflicts(tasks: &[Task]) -> Vec<(usize, usize)> {
+    let windows: Vec<(usize, i64, i64)> = tasks
+        .iter()
+        .enumerate()
+        .filter_map(|(i, t)| {
+            let start = minutes_of(datetime::parse_time(&t.start).ok()?);
+            Some((i, start, start + i64::from(t.estimate.max(0))))
+        })
+        .collect();
+    overlapping_pairs(&windows)
+}
+
+/// One line per overlapping pair of tasks, like "09:00 Standup (15 min) overlaps 09:10 Emails (20 min)"
+pub fn describe_task_conflicts(tasks: &[Task]) -> Vec<String> {
+    task_conflicts(tasks)
+        .into_iter()
+        .map(|(a, b)| {
+            let (a, b) = (&tasks[a], &tasks[b]);
+            format!(
+                "{} {} ({} min) overlaps {} {} ({} min)",
+                a.start, a.name, a.estimate, b.start, b.name, b.estimate
+            )
+        })
+        .collect()
+}
+
+/// Pairs of log rows with sessions that overlap, as indexes into logs
+/// Sessions ending before they start are taken to cross midnight
+pub fn log_conflicts(logs: &[Log]) -> Vec<(usize, usize)> {
+    let mut windows = Vec::new();
+    for (i, log) in logs.iter().enumerate() {
+        for session in &log.sessions {
+            let start = datetime::parse_time(&session.start).map(minutes_of);
+            let end = datetime::parse_time(&session.end).map(minutes_of);
+            if let (Ok(start), Ok(end)) = (start, end) {
+                let end = if end < start { end + 24 * 60 } else { end };
+                windows.push((i, start, end));
+            }
+        }
+    }
+    overlapping_pairs(&windows)
+}
+
+/// Windows are (owner, start, end), windows of the same owner never conflict
+fn overlapping_pairs(windows: &[(usize, i64, i64)]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    for (n, (a, a_start, a_end)) in windows.iter().enumerate() {
+        for (b, b_start, b_end) in &windows[n + 1..] {
+            if a != b && a_start < b_end && b_start < a_end {
+                pairs.push((*a.min(b), *a.max(b)));
+            }
+        }
+    }
+    pairs.sort_unstable();
+    pairs.dedup();
+    pairs
+}
+
+fn config_minutes(time: &str) -> Result<i64> {
+    datetime::parse_time(time)
+        .map(minutes_of)
+        .map_err(|e| Error::Config(e.to_string()))
+}
+
+fn minutes_of(time: NaiveTime) -> i64 {
+    i64::from(time.num_seconds_from_midnight() / 60)
+}
+
+fn format_minutes(minutes: i64) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workday() -> Schedule {
+        Schedule::new("09:00", "17:00", &["12:00-13:00".to_string()]).unwrap()
+    }
+
+    fn starts(tasks: &[Task]) -> Vec<(&str, &str)> {
+        tasks
+            .iter()
+            .map(|t| (t.name.as_str(), t.start.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn working_minutes_leave_out_breaks() {
+        assert_eq!(workday().working_minutes(), 7 * 60);
+    }
+
+    #[test]
+    fn flexible_tasks_fill_the_earliest_free_slots() {
+        let tasks = vec![
+            Task::named("Standup").at("09:00", 15),
+            Task::named("Emails"),
+            Task::named("Review").at("", 60),
+        ];
+        let (scheduled, unscheduled) = workday().assign(tasks);
+
+        assert!(unscheduled.is_empty());
+        assert_eq!(
+            starts(&scheduled),
+            vec![
+                ("Standup", "09:00"),
+                ("Emails", "09:15"),
+                ("Review", "09:45")
+            ]
+        );
+    }
+
+    #[test]
+    fn task_that_would_cross_a_break_moves_after_it() {
+        let tasks = vec![
+            Task::named("Morning").at("09:00", 150),
+            Task::named("Report").at("", 60),
+        ];
+        let (scheduled, _) = workday().assign(tasks);
+
+        // 11:30 leaves only 30 minutes before the break, tasks are never split around it
+        assert_eq!(
+            starts(&scheduled),
+            vec![("Morning", "09:00"), ("Report", "13:00")]
+        );
+    }
+
+    #[test]
+    fn task_past_work_end_is_unscheduled() {
+        let tasks = vec![
+            Task::named("Morning").at("09:00", 180),
+            Task::named("Afternoon").at("13:00", 210),
+            Task::named("Long").at("", 60),
+            Task::named("Short"),
+        ];
+        let (scheduled, unscheduled) = workday().assign(tasks);
+
+        assert_eq!(starts(&unscheduled), vec![("Long", "")]);
+        assert_eq!(
+            starts(&scheduled),
+            vec![
+                ("Morning", "09:00"),
+                ("Afternoon", "13:00"),
+                ("Short", "16:30"),
+                ("Long", "")
+            ]
+        );
+    }
+
+    #[test]
+    fn task_longer_than_any_gap_is_unscheduled() {
+        let (_, unscheduled) = workday().assign(vec![Task::named("Marathon").at("", 5 * 60)]);
+
+        assert_eq!(unscheduled.len(), 1);
+    }
+
+    #[test]
+    fn malformed_config_is_rejected() {
+        let breaks = |b: &str| vec![b.to_string()];
+        assert!(Schedule::new("09:00", "17:00", &breaks("12:00")).is_err());
+        assert!(Schedule::new("09:00", "17:00", &breaks("13:00-12:00")).is_err());
+        assert!(Schedule::new("09:00", "17:00", &breaks("noon-13:00")).is_err());
+        assert!(Schedule::new("17:00", "09:00", &[]).is_err());
+        assert!(Schedule::new("25:00", "17:00", &[]).is_err());
+    }
+
+    #[test]
+    fn overlapping_tasks_conflict() {
+        let tasks = vec![
+            Task::named("Standup").at("09:00", 15),
+            Task::named("Emails").at("09:10", 20),
+            Task::named("Flexible"),
+            Task::named("Lunch").at("12:00", 60),
+        ];
+
+        assert_eq!(task_conflicts(&tasks), vec![(0, 1)]);
+        assert_eq!(
+            describe_task_conflicts(&tasks),
+            vec!["09:00 Standup (15 min) overlaps 09:10 Emails (20 min)".to_string()]
+        );
+    }
+
+    #[test]
+    fn tasks_that_only_touch_do_not_conflict() {
+        let tasks = vec![
+            Task::named("Standup").at("09:00", 15),
+            Task::named("Emails").at("09:15", 30),
+        ];
+
+        assert!(task_conflicts(&tasks).is_empty());
+    }
+
+    #[test]
+    fn overlapping_sessions_of_different_logs_conflict() {
+        let logs = vec![
+            Log::with_sessions("Report", &[("09:00", "10:00"), ("14:00", "15:00")]),
+            Log::with_sessions("Call", &[("14:30", "14:45")]),
+            Log::with_sessions("Emails", &[("10:00", "10:30")]),
+        ];
+
+        assert_eq!(log_conflicts(&logs), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn sessions_of_the_same_log_never_conflict() {
+        let logs = vec![Log::with_sessions(
+            "Report",
+            &[("09:00", "10:00"), ("09:30", "10:30")],
+        )];
+
+        assert!(log_conflicts(&logs).is_empty());
+    }
+
+    #[test]
+    fn overnight_session_conflicts_after_midnight() {
+        let logs = vec![
+            Log::with_sessions("Deploy", &[("23:00", "01:00")]),
+            Log::with_sessions("Backup", &[("23:30", "23:45")]),
+            Log::with_sessions("Morning", &[("08:00", "09:00")]),
+        ];
+
+        assert_eq!(log_conflicts(&logs), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn unreadable_sessions_are_ignored() {
+        let logs = vec![
+            Log::with_sessions("Report", &[("09:00", "10:00")]),
+            Log::with_sessions("Typo", &[("9ish", "10:00")]),
+        ];
+
+        assert!(log_conflicts(&logs).is_empty());
+    }
+}
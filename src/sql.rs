@@ -1,17 +1,22 @@
 use crate::Task;
 use rusqlite::types::ToSql;
 use rusqlite::NO_PARAMS;
-use rusqlite::{params, Connection, Result};
+use rusqlite::{params, Connection, OptionalExtension, Result};
 use std::fmt;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Error};
+use std::fs;
 use std::str;
 use std::rc::Rc;
 use rusqlite::types::Value as SqlValue;
 
 use crate::datetime;
+use crate::error::{self, Error};
+use crate::ics::{CalendarEvent, CALENDAR_PROJECT};
+use crate::parser::{self, LineTemplate, LogEntry};
+use crate::query::{RepeatFilter, SavedFilter, TaskQuery};
+use crate::schedule::{self, Schedule};
 use crate::Log;
 use crate::Note;
+use crate::Session;
 
 /// Creates tables in SQLite Database
 pub fn init(conn: &Connection) -> Result<()> {
@@ -33,6 +38,36 @@ pub fn init(conn: &Connection) -> Result<()> {
         )",
         NO_PARAMS,
     )?;
+    conn.execute(
+        "create table if not exists tags (
+            id INTEGER,
+            tag TEXT NOT NULL,
+            PRIMARY KEY(id, tag)
+        )",
+        NO_PARAMS,
+    )?;
+    conn.execute(
+        "create table if not exists log_session (
+            id INTEGER PRIMARY KEY AUTOINCREMENT, log_id INTEGER,
+            start TEXT, end TEXT
+         )",
+        NO_PARAMS,
+    )?;
+    conn.execute(
+        "create table if not exists completion (
+            id INTEGER,
+            date TEXT,
+            PRIMARY KEY(id, date)
+        )",
+        NO_PARAMS,
+    )?;
+    conn.execute(
+        "create table if not exists saved_filters (
+            name TEXT PRIMARY KEY, status TEXT, project TEXT, tags TEXT,
+            next_from TEXT, next_to TEXT, repeat TEXT, text TEXT
+         )",
+        NO_PARAMS,
+    )?;
     conn.execute(
         "create table if not exists log (
             id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, notes TEXT,
@@ -41,16 +76,38 @@ pub fn init(conn: &Connection) -> Result<()> {
          )",
         NO_PARAMS,
     )?;
+    conn.execute(
+        "create table if not exists calendar_events (
+            uid TEXT,
+            date TEXT,
+            task_id INTEGER,
+            PRIMARY KEY(uid, date)
+        )",
+        NO_PARAMS,
+    )?;
 
     Ok(())
 }
 
-fn execute_insert_query(conn: &Connection, query: &str, param_slice: &[&ToSql]) -> Result<()> {
+fn execute_insert_query(conn: &Connection, query: &str, param_slice: &[&dyn ToSql]) -> Result<()> {
     conn.execute(query, param_slice)?;
 
     Ok(())
 }
 
+/// Runs given closure inside a single transaction
+/// Rolls back every change made by the closure if it returns an error
+pub fn in_transaction<F>(conn: &Connection, f: F) -> Result<()>
+where
+    F: FnOnce(&Connection) -> Result<()>,
+{
+    let tx = conn.unchecked_transaction()?;
+    f(&tx)?;
+    tx.commit()?;
+
+    Ok(())
+}
+
 pub fn add_task(conn: &Connection, t: Task) -> Result<()> {
     let query = "INSERT INTO tasks (status, name, project, start,
         estimate, repeat, next)
@@ -87,6 +144,20 @@ pub fn add_note(conn: &Connection, id: i32, start: &str, end: &str, text: &str)
     Ok(())
 }
 
+pub fn add_tag(conn: &Connection, task_id: &i32, tag: &str) -> Result<()> {
+    let mut stmt = conn.prepare("INSERT OR IGNORE INTO tags (id, tag) VALUES (?, ?)")?;
+    stmt.execute(params![task_id, tag])?;
+
+    Ok(())
+}
+
+pub fn delete_tag(conn: &Connection, task_id: &i32, tag: &str) -> Result<()> {
+    let mut stmt = conn.prepare("DELETE FROM tags WHERE id = ? and tag = ?")?;
+    stmt.execute(params![task_id, tag])?;
+
+    Ok(())
+}
+
 pub fn modify_date(conn: &Connection, task_id: &i32, value: &str) -> Result<()> {
     let mut stmt = conn.prepare("UPDATE tasks SET next = ? WHERE id = ?")?;
     stmt.execute(params![value, task_id])?;
@@ -130,7 +201,7 @@ pub fn modify_status(conn: &Connection, task_id: &i32, value: &str) -> Result<()
 }
 
 pub fn get_all_notes(conn: &Connection, id_vec: &[i32]) -> Result<Vec<Note>> {
-    rusqlite::vtab::array::load_module(&conn)?;
+    rusqlite::vtab::array::load_module(conn)?;
 
     let mut stmt = conn.prepare("SELECT t.id, t.name,
                                                  ifnull(n.start, ''),
@@ -144,7 +215,7 @@ pub fn get_all_notes(conn: &Connection, id_vec: &[i32]) -> Result<Vec<Note>> {
                                                  WHERE t.id IN rarray(?)")?;
 
     let note_ids: Vec<SqlValue> = id_vec
-        .into_iter()
+        .iter()
         .map(|i| SqlValue::from(*i))
         .collect();
     let note_ids_ptr = Rc::new(note_ids);
@@ -161,8 +232,7 @@ pub fn get_all_notes(conn: &Connection, id_vec: &[i32]) -> Result<Vec<Note>> {
     let mut selected_notes: Vec<Note> = Vec::new();
 
     for note in notes_iter {
-        let n = note.unwrap();
-        selected_notes.push(n);
+        selected_notes.push(note?);
     }
 
     Ok(selected_notes)    
@@ -171,6 +241,8 @@ pub fn get_all_notes(conn: &Connection, id_vec: &[i32]) -> Result<Vec<Note>> {
 pub fn delete_task_by_id(conn: &Connection, id: &i32) -> Result<()> {
     let mut stmt = conn.prepare("DELETE FROM tasks WHERE id=?")?;
     stmt.execute(params![id])?;
+    let mut stmt = conn.prepare("DELETE FROM tags WHERE id=?")?;
+    stmt.execute(params![id])?;
 
     Ok(())
 }
@@ -182,385 +254,712 @@ pub fn delete_note_by_id_date(conn: &Connection, id: &i32, date: &str) -> Result
     Ok(())
 }
 
-fn query_to_vec_task(conn: &Connection, query: &str) -> Result<Vec<Task>> {
-    let mut stmt = conn.prepare(&query)?;
+pub fn filter_by_status(conn: &Connection, status: &str) -> Result<Vec<Task>> {
+    TaskQuery::new().status(status).run(conn)
+}
 
-    let task_iter = stmt.query_map(params![], |row| {
-        Ok(Task {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            project: row.get(2)?,
-            start: row.get(3)?,
-            estimate: row.get(4)?,
-            repeat: row.get(5)?,
-            next: row.get(6)?,
-            notes: row.get(7)?,
-            status: row.get(8)?,
-        })
-    })?;
+pub fn filter_by_date(conn: &Connection, date: &str) -> Result<Vec<Task>> {
+    TaskQuery::new().on(date).notes_as_of(date).run(conn)
+}
 
-    let mut vec = Vec::new();
-    for task in task_iter {
-        let t = task.unwrap();
-        vec.push(t);
-    }
+/// One-off tasks on the given date and routines that fall on it
+/// Routines are projected forward from their next date using their repeat string,
+/// so plans can be made for any day ahead
+pub fn filter_by_date_plan(conn: &Connection, date: &str) -> Result<Vec<Task>> {
+    let one_off = TaskQuery::new()
+        .on(date)
+        .status("ACTIVE")
+        .repeat(RepeatFilter::OneOff)
+        .notes_as_of(date)
+        .run(conn)?;
+    let routines = TaskQuery::new()
+        .next_to(date)
+        .status("ACTIVE")
+        .repeat(RepeatFilter::Routine)
+        .notes_as_of(date)
+        .run(conn)?
+        .into_iter()
+        .filter(|t| datetime::routine_falls_on(&t.next, &t.repeat, date));
 
-    Ok(vec)
+    let mut task_vector: Vec<Task> = one_off.into_iter().chain(routines).collect();
+    task_vector.sort_by(|a, b| a.start.cmp(&b.start));
+    Ok(task_vector)
 }
 
-pub fn filter_by_status(conn: &Connection, status: &str) -> Result<Vec<Task>> {
-    let query = format!("SELECT id, name, project, start, estimate, repeat, next,
-                 '', status
-                 FROM tasks
-                 WHERE status = '{}'",
-                 status);
-    let task_vector = query_to_vec_task(conn, &query)?;
+/// Estimated minutes planned on a date, routines included
+pub fn planned_minutes(conn: &Connection, date: &str) -> Result<i32> {
+    Ok(filter_by_date_plan(conn, date)?.iter().map(|t| t.estimate).sum())
+}
 
-    Ok(task_vector)
+/// Days after an overloaded date that overload_moves looks at for room
+pub const CAPACITY_SEARCH_DAYS: i64 = 30;
+
+/// Proposes moving one-off tasks off a date until its estimates fit the capacity
+/// Tasks imported from a calendar stay, but their estimates count towards the load
+/// Tasks have no priority, so tasks without a start time are moved first, then
+/// the rest, most recently added first within each group, each to the first of
+/// the next CAPACITY_SEARCH_DAYS days that still has room for it
+pub fn overload_moves(
+    conn: &Connection,
+    date: &str,
+    capacity: i32,
+) -> error::Result<Vec<(Task, String)>> {
+    let mut load = planned_minutes(conn, date)?;
+    let mut candidates: Vec<Task> = TaskQuery::new()
+        .on(date)
+        .status("ACTIVE")
+        .repeat(RepeatFilter::OneOff)
+        .movable()
+        .run(conn)?;
+    candidates.sort_by_key(|t| (!t.start.trim().is_empty(), std::cmp::Reverse(t.id)));
+
+    let mut other_loads: Vec<(String, i32)> = Vec::new();
+    for n in 1..=CAPACITY_SEARCH_DAYS {
+        let day = datetime::date_plus_n(date, n)?;
+        let minutes = planned_minutes(conn, &day)?;
+        other_loads.push((day, minutes));
+    }
+
+    let mut moves = Vec::new();
+    for task in candidates {
+        if load <= capacity {
+            break;
+        }
+        let free_day = other_loads
+            .iter_mut()
+            .find(|(_, minutes)| *minutes + task.estimate <= capacity);
+        if let Some((day, minutes)) = free_day {
+            *minutes += task.estimate;
+            load -= task.estimate;
+            moves.push((task, day.clone()));
+        }
+    }
+
+    Ok(moves)
 }
 
-pub fn filter_by_date(conn: &Connection, date: &str) -> Result<Vec<Task>> {
-    let query = format!(
-        "SELECT t.id, t.name, t.project, t.start, t.estimate,
-                         t.repeat, t.next, ifnull(n.notetext, ''), t.status
-                         FROM tasks as t
-                         LEFT OUTER JOIN (
-                            SELECT *
-                            FROM (
-                                SELECT id, MAX(start) as start, notetext
-                                FROM note
-                                GROUP BY id)
-                            WHERE date(start) <= '{}'
-                            ORDER BY id
-                        ) as n
-                         ON t.id = n.id
-                         WHERE t.next = '{}' ORDER BY t.start",
-        date, date
-    );
-    let task_vector = query_to_vec_task(conn, &query)?;
+/// Active one-off tasks dated before the given date that have no log entry on their date
+/// Tasks imported from a calendar are left out, a missed appointment isn't moved
+pub fn filter_by_overdue(conn: &Connection, date: &str) -> error::Result<Vec<Task>> {
+    let day_before = datetime::date_plus_n(date, -1)?;
+    // Tasks without a date have an empty next, which sorts before any date
+    let tasks = TaskQuery::new()
+        .next_from("0000-01-01")
+        .next_to(&day_before)
+        .status("ACTIVE")
+        .repeat(RepeatFilter::OneOff)
+        .movable()
+        .run(conn)?;
+
+    let mut overdue = Vec::new();
+    for task in tasks {
+        let logged: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM log WHERE name = ? AND project = ? AND date = ?)",
+            params![task.name, task.project, task.next],
+            |row| row.get(0),
+        )?;
+        if !logged {
+            overdue.push(task);
+        }
+    }
 
-    Ok(task_vector)
+    Ok(overdue)
 }
 
-pub fn filter_by_date_plan(conn: &Connection, date: &str) -> Result<Vec<Task>> {
-    let query = format!(
-        "SELECT t.id, t.name, t.project, t.start, t.estimate,
-        t.repeat, t.next, ifnull(n.notetext, ''), t.status
-        FROM tasks as t
-        LEFT OUTER JOIN (
-			SELECT id, MAX(start), notetext
-			FROM (
-				SELECT id, start, notetext
-				FROM note
-				WHERE date(start) <= '{}'
-				ORDER BY id)
-			GROUP BY id
-        ) as n
-        on t.id = n.id
-		WHERE t.next = '{}' AND t.status = 'ACTIVE'
-        ORDER BY t.start",
-        date, date
-    );
-    let task_vector = query_to_vec_task(conn, &query)?;
-    Ok(task_vector)
+/// Active tasks for a calendar export, one-off tasks dated in the range
+/// and routines that fall on a day in it
+/// A routine's next date is moved to its first day in the range, so the
+/// calendar doesn't repeat it before from
+pub fn filter_by_calendar_range(conn: &Connection, from: &str, to: &str) -> Result<Vec<Task>> {
+    let one_off = TaskQuery::new()
+        .next_from(from)
+        .next_to(to)
+        .status("ACTIVE")
+        .repeat(RepeatFilter::OneOff)
+        .run(conn)?;
+    let routines = TaskQuery::new()
+        .next_to(to)
+        .status("ACTIVE")
+        .repeat(RepeatFilter::Routine)
+        .run(conn)?
+        .into_iter()
+        .filter_map(|mut t| {
+            let first = datetime::first_routine_date(&t.next, &t.repeat, from)?;
+            if first.as_str() > to {
+                return None;
+            }
+            t.next = first;
+            Some(t)
+        });
+
+    Ok(one_off.into_iter().chain(routines).collect())
 }
 
-pub fn filter_by_date_plan_tom(conn: &Connection, tomorrow: &str, today: &str) -> Result<Vec<Task>> {
-    let query = format!(
-        "SELECT t.id, t.name, t.project, t.start, t.estimate,
-        t.repeat, t.next, ifnull(n.notetext, ''), t.status
-        FROM tasks as t
-        LEFT OUTER JOIN (
-			SELECT id, MAX(start), notetext
-			FROM (
-				SELECT id, start, notetext
-				FROM note
-				WHERE date(start) <= '{}'
-				ORDER BY id)
-			GROUP BY id
-        ) as n
-        on t.id = n.id
-        WHERE t.status = 'ACTIVE'
-        AND ((t.repeat = '' AND t.next = '{}')
-		OR (t.repeat <> '' AND t.next = '{}'))
-        ORDER BY t.start",
-        tomorrow, tomorrow, today
-    );
-    let task_vector = query_to_vec_task(conn, &query)?;
-    Ok(task_vector)
+/// Active one-off tasks that have no date yet
+pub fn filter_by_undated(conn: &Connection) -> Result<Vec<Task>> {
+    TaskQuery::new()
+        .on("")
+        .status("ACTIVE")
+        .repeat(RepeatFilter::OneOff)
+        .run(conn)
 }
 
 pub fn filter_by_project(conn: &Connection, project: String) -> Result<Vec<Task>> {
-    let query = format!(
-        "SELECT id, name, project, start, estimate, repeat, next, '', status
-        FROM tasks
-        WHERE project = '{}' ORDER BY start",
-        project
-    );
-
-    let task_vector = query_to_vec_task(conn, &query)?;
-
-    Ok(task_vector)
+    TaskQuery::new().project(&project).run(conn)
 }
 
 pub fn filter_by_routine(conn: &Connection) -> Result<Vec<Task>> {
-    let query = "SELECT id, name, project, start, estimate, repeat, next,
-                 '', status FROM tasks
-                 WHERE repeat <> ''
-                 ORDER BY start";
-
-    let task_vector = query_to_vec_task(conn, &query)?;
-
-    Ok(task_vector)
+    TaskQuery::new().repeat(RepeatFilter::Routine).run(conn)
 }
 
 pub fn filter_by_repeat(conn: &Connection, repeat: String) -> Result<Vec<Task>> {
-    let query = format!(
-        "SELECT id, name, project, start, estimate, repeat, next, '', status
-         FROM tasks
-         WHERE repeat = '{}'
-         ORDER BY start",
-        repeat
-    );
+    TaskQuery::new().repeat(RepeatFilter::Exactly(repeat)).run(conn)
+}
+
+pub fn filter_by_id(conn: &Connection, id_vec: Vec<i32>) -> Result<Vec<Task>> {
+    TaskQuery::new().ids(&id_vec).run(conn)
+}
 
-    let task_vector = query_to_vec_task(conn, &query)?;
+/// Saves a filter, replacing any saved filter with the same name
+pub fn save_filter(conn: &Connection, f: &SavedFilter) -> Result<()> {
+    let query = "INSERT OR REPLACE INTO saved_filters (name, status, project, tags,
+        next_from, next_to, repeat, text)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)";
+    let param_slice = params![
+        f.name, f.status, f.project, f.tags, f.next_from, f.next_to, f.repeat, f.text
+    ];
+    execute_insert_query(conn, query, param_slice)?;
 
-    Ok(task_vector)
+    Ok(())
 }
 
-pub fn filter_by_id(conn: &Connection, id_vec: Vec<i32>) -> Result<Vec<Task>> {
+pub fn get_saved_filters(conn: &Connection) -> Result<Vec<SavedFilter>> {
+    let mut stmt = conn.prepare(
+        "SELECT name, status, project, tags, next_from, next_to, repeat, text
+         FROM saved_filters ORDER BY name",
+    )?;
 
-    let mut ids_string: String = "(".to_string();
+    let filter_iter = stmt.query_map(NO_PARAMS, |row| {
+        Ok(SavedFilter {
+            name: row.get(0)?,
+            status: row.get(1)?,
+            project: row.get(2)?,
+            tags: row.get(3)?,
+            next_from: row.get(4)?,
+            next_to: row.get(5)?,
+            repeat: row.get(6)?,
+            text: row.get(7)?,
+        })
+    })?;
 
-    for id in id_vec {
-        ids_string = ids_string + &id.to_string() + ", ";
+    let mut vec = Vec::new();
+    for saved_filter in filter_iter {
+        vec.push(saved_filter?);
     }
 
-    let target_length = ids_string.len() - 2;
-    ids_string.truncate(target_length);
-    ids_string = ids_string + ")";
-
-    let query = format!(
-        "SELECT id, name, project, start, estimate, repeat, next, '', status
-         FROM tasks
-         WHERE id IN {}
-         ORDER BY start",
-        ids_string
-    );
-    let task_vector = query_to_vec_task(conn, &query)?;
-    Ok(task_vector.to_vec())
+    Ok(vec)
 }
 
-pub fn generate_today_plan(conn: &Connection) -> Result<String> {
-    let vec = filter_by_date_plan(conn, &datetime::yyyymmdd_today_plus_n(0));
-    let plan_string = vector_to_daily_plan(vec)?;
+pub fn delete_saved_filter(conn: &Connection, name: &str) -> Result<()> {
+    let mut stmt = conn.prepare("DELETE FROM saved_filters WHERE name = ?")?;
+    stmt.execute(params![name])?;
 
-    Ok(plan_string)
+    Ok(())
 }
 
-pub fn generate_tomorrow_plan(conn: &Connection) -> Result<String> {
-    let vec = filter_by_date_plan_tom(conn, &datetime::yyyymmdd_today_plus_n(1), &datetime::yyyymmdd_today_plus_n(0));
-    let plan_string = vector_to_daily_plan(vec)?;
-
-    Ok(plan_string)
+/// How a generated plan is written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlanLayout<'a> {
+    /// One task line after another
+    Flat,
+    /// A heading per project with its total, the daily total on top and routines on their own
+    Grouped,
+    /// Merged into the text of an existing plan file, keeping its layout
+    Merge(&'a str),
 }
 
-fn vector_to_daily_plan(vec: Result<Vec<Task>>) -> Result<String> {
-    let vec2 = vec.unwrap();
-    let mut output_string = "".to_owned();
-    for task in vec2 {
-        let tmp = task.to_string().to_owned();
-        output_string.push_str(&tmp);
+/// Plan for a date, flexible tasks get start times when a schedule is given
+/// Also returns the flexible tasks that didn't fit into working hours
+pub fn generate_plan(
+    conn: &Connection,
+    date: &str,
+    template: &LineTemplate,
+    schedule: Option<&Schedule>,
+    layout: PlanLayout,
+) -> Result<(String, Vec<Task>)> {
+    let vec = filter_by_date_plan(conn, date)?;
+    let (vec, unscheduled) = match schedule {
+        Some(schedule) => schedule.assign(vec),
+        None => (vec, Vec::new()),
+    };
+    let conflicts = schedule::describe_task_conflicts(&vec);
+    let mut plan_string = match layout {
+        PlanLayout::Flat => vector_to_daily_plan(vec, template),
+        PlanLayout::Grouped => vector_to_grouped_plan(vec, template, date),
+        PlanLayout::Merge(existing) => parser::merge_plan(existing, &vec, template),
+    };
+    if !conflicts.is_empty() {
+        plan_string.push_str(&format!("\n{}\n", parser::CONFLICTS_HEADING));
+        for conflict in conflicts {
+            plan_string.push_str(&conflict);
+            plan_string.push('\n');
+        }
     }
 
-    Ok(output_string)
+    Ok((plan_string, unscheduled))
 }
 
-#[derive(Debug)]
-pub struct LogItem {
-    pub name: String,
-    pub notes: String,
-    pub project: String,
-    pub date: String,
-    pub start: String,
-    pub end: String,
-    pub estimate: i32,
-    pub review: String,
+/// Markdown document for the ISO week containing the date, with each day's plan
+/// and estimated load, followed by tasks that still need a date
+pub fn generate_week_plan(conn: &Connection, date: &str, template: &LineTemplate) -> error::Result<String> {
+    let (week, dates) = datetime::iso_week(date)?;
+    let mut output_string = format!("# {}\n", week);
+    let mut week_total = 0;
+
+    for date in &dates {
+        let vec = filter_by_date_plan(conn, date)?;
+        let load: i32 = vec.iter().map(|t| t.estimate).sum();
+        week_total += load;
+        output_string.push_str(&format!(
+            "\n## {} {} ({} min)\n",
+            datetime::weekday_name(date),
+            date,
+            load
+        ));
+        output_string.push_str(&vector_to_daily_plan(vec, template));
+    }
+
+    let undated = filter_by_undated(conn)?;
+    output_string.push_str("\n## Needs a date\n");
+    output_string.push_str(&vector_to_daily_plan(undated, template));
+    output_string.push_str(&format!("\nTotal estimated: {} min\n", week_total));
+
+    Ok(output_string)
 }
 
-impl LogItem {
-    fn set_name(&mut self, name: String) {
-        self.name = name;
+/// Routines first, then one section per project in alphabetical order
+/// Headings are skipped by the importer, so the file reads back like a flat plan
+fn vector_to_grouped_plan(vec: Vec<Task>, template: &LineTemplate, date: &str) -> String {
+    let total: i32 = vec.iter().map(|t| t.estimate).sum();
+    let mut output_string = format!("# {} ({} min planned)\n", date, total);
+
+    let (routines, one_off): (Vec<Task>, Vec<Task>) =
+        vec.into_iter().partition(|t| !t.repeat.is_empty());
+    let mut sections: Vec<(String, Vec<Task>)> = Vec::new();
+    if !routines.is_empty() {
+        sections.push(("Routines".to_string(), routines));
     }
-
-    fn set_notes(&mut self, notes: String) {
-        self.notes = notes;
+    let mut projects: std::collections::BTreeMap<String, Vec<Task>> = Default::default();
+    for task in one_off {
+        projects.entry(task.project.clone()).or_default().push(task);
     }
+    sections.extend(projects);
 
-    fn set_project(&mut self, project: String) {
-        self.project = project;
+    for (heading, tasks) in sections {
+        let minutes: i32 = tasks.iter().map(|t| t.estimate).sum();
+        output_string.push_str(&format!("\n## {} ({} min)\n", heading, minutes));
+        output_string.push_str(&vector_to_daily_plan(tasks, template));
     }
 
-    fn set_estimate(&mut self, estimate: i32) {
-        self.estimate = estimate;
-    }
+    output_string
+}
 
-    fn set_start(&mut self, start: String) {
-        self.start = start;
+fn vector_to_daily_plan(vec: Vec<Task>, template: &LineTemplate) -> String {
+    let mut output_string = "".to_owned();
+    for task in vec {
+        output_string.push_str(&template.render_line(&task));
     }
 
-    fn set_end(&mut self, end: String) {
-        self.end = end;
-    }
+    output_string
+}
 
-    fn set_review(&mut self, review: String) {
-        self.review = review;
-    }
+/// How an import treats rows already stored for the same date
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportMode {
+    /// Delete the date's rows before saving the file's entries
+    Replace,
+    /// Update rows with the same name and start time, add the rest
+    Merge,
 }
 
-impl Default for LogItem {
-    fn default() -> LogItem {
-        LogItem {
-            name: "".to_string(),
-            notes: "".to_string(),
-            project: "".to_string(),
-            date: "".to_string(),
-            start: "".to_string(),
-            end: "".to_string(),
-            estimate: 0,
-            review: "".to_string(),
-        }
+/// What an import changed
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub saved: usize,
+    pub done: usize,
+    pub rolled_over: usize,
+    pub routines_completed: usize,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entries saved, {} tasks done, {} tasks rolled over, {} routines completed",
+            self.saved, self.done, self.rolled_over, self.routines_completed
+        )
     }
 }
 
-impl fmt::Display for LogItem {
+/// What a calendar import changed
+#[derive(Debug, Default)]
+pub struct CalendarImportSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+}
+
+impl fmt::Display for CalendarImportSummary {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(
+        write!(
             f,
-            "{} {} {} {} {} {} {}",
-            self.name, self.notes, self.project, self.estimate, self.start, self.end, self.review
+            "{} events added, {} events updated, {} cancelled or missing events removed",
+            self.added, self.updated, self.removed
         )
     }
 }
 
-pub fn log_to_database(conn: &Connection, log_path: String, date: String) -> Result<(), Error> {
-    let input = File::open(log_path)?;
-    let buffered = BufReader::new(input);
+/// Saves calendar events read for the dates from `from` to `to` as fixed-time tasks
+/// in a single transaction
+/// Events imported before, matched by their key, update their task, moving it when the date
+/// changed, and a task deleted since is added again. Events that are cancelled, or were
+/// imported for a date in the range but are no longer in the calendar, delete their task
+/// while it is still ACTIVE
+pub fn calendar_events_to_database(
+    conn: &Connection,
+    events: &[CalendarEvent],
+    from: &str,
+    to: &str,
+) -> Result<CalendarImportSummary> {
+    let mut summary = CalendarImportSummary::default();
+
+    in_transaction(conn, |tx| {
+        for event in events.iter().filter(|e| !e.cancelled) {
+            let task_id: Option<i32> = tx
+                .query_row(
+                    "SELECT task_id FROM calendar_events WHERE uid = ?",
+                    params![event.key],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            if let Some(id) = task_id {
+                let changed = tx.execute(
+                    "UPDATE tasks SET name = ?, start = ?, estimate = ?, next = ? WHERE id = ?",
+                    params![event.summary, event.start, event.minutes, event.date, id],
+                )?;
+                if changed > 0 {
+                    tx.execute(
+                        "UPDATE calendar_events SET date = ? WHERE uid = ?",
+                        params![event.date, event.key],
+                    )?;
+                    summary.updated += 1;
+                    continue;
+                }
+                tx.execute("DELETE FROM calendar_events WHERE uid = ?", params![event.key])?;
+            }
 
-    let mut one_log = LogItem::default();
-    one_log.date = date;
+            add_task(
+                tx,
+                Task {
+                    id: 0,
+                    status: "ACTIVE".to_string(),
+                    name: event.summary.clone(),
+                    notes: "".to_string(),
+                    project: CALENDAR_PROJECT.to_string(),
+                    start: event.start.clone(),
+                    estimate: event.minutes,
+                    repeat: "".to_string(),
+                    next: event.date.clone(),
+                },
+            )?;
+            tx.execute(
+                "INSERT INTO calendar_events (uid, date, task_id) VALUES (?, ?, ?)",
+                params![event.key, event.date, tx.last_insert_rowid()],
+            )?;
+            summary.added += 1;
+        }
 
-    for line in buffered.lines() {
-        let l = match line {
-            Ok(line) => line,
-            Err(err) => panic!("Error reading line"),
-        };
-        if l.starts_with("- ") {
-            if one_log.start != "" && one_log.end != "" {
-                logitem_to_database(conn, &mut one_log).ok();
-                reset_review_fields(&mut one_log).ok();
+        let mut stale: Vec<(String, i32)> = Vec::new();
+        let mut stmt =
+            tx.prepare("SELECT uid, task_id FROM calendar_events WHERE date >= ? AND date <= ?")?;
+        let rows = stmt.query_map(params![from, to], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (key, task_id): (String, i32) = row?;
+            let current = events.iter().any(|e| !e.cancelled && e.key == key);
+            if !current {
+                stale.push((key, task_id));
+            }
+        }
+        for event in events.iter().filter(|e| e.cancelled) {
+            let task_id: Option<i32> = tx
+                .query_row(
+                    "SELECT task_id FROM calendar_events WHERE uid = ?",
+                    params![event.key],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(task_id) = task_id {
+                if !stale.iter().any(|(key, _)| *key == event.key) {
+                    stale.push((event.key.clone(), task_id));
+                }
             }
-            process_task_line(l, &mut one_log).ok();
-        } else if l.starts_with("  -") {
-            process_indented_line(l, &mut one_log).ok();
         }
-    }
-    logitem_to_database(conn, &mut one_log).ok();
 
-    Ok(())
+        for (key, task_id) in stale {
+            let active = TaskQuery::new().ids(&[task_id]).status("ACTIVE").run(tx)?;
+            if !active.is_empty() {
+                delete_task_by_id(tx, &task_id)?;
+                summary.removed += 1;
+            }
+            tx.execute("DELETE FROM calendar_events WHERE uid = ?", params![key])?;
+        }
+        Ok(())
+    })?;
+
+    Ok(summary)
 }
 
-fn logitem_to_database(conn: &Connection, one_log: &mut LogItem) -> Result<()> {
-    let query = "INSERT INTO log (name, notes, project, date,
-        start, end, estimate, review) VALUES
-        (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)";
-    let param_slice = params![
-        one_log.name,
-        one_log.notes,
-        one_log.project,
-        one_log.date,
-        one_log.start,
-        one_log.end,
-        one_log.estimate,
-        one_log.review
-    ];
-    execute_insert_query(conn, query, param_slice)?;
+/// Reads a markdown log and saves every entry with a finished session to the log table
+/// Entries are saved in a single transaction, so nothing changes if any query fails
+/// Returns the parser warnings so they can be shown to the user
+pub fn log_to_database(
+    conn: &Connection,
+    log_path: String,
+    date: String,
+    mode: ImportMode,
+    template: &LineTemplate,
+) -> error::Result<(ImportSummary, Vec<Error>)> {
+    let text = fs::read_to_string(log_path)?;
+    let parsed = parser::parse_log(&text, template);
+    let summary = log_entries_to_database(conn, &parsed.entries, &date, mode)?;
+
+    Ok((summary, parsed.warnings))
+}
 
-    Ok(())
+/// Replaces the date's log rows with the markdown log's entries but leaves tasks alone
+/// Used to keep rows current while the day is still being logged, since applying
+/// checkboxes can't be undone by a later import
+pub fn sync_log_to_database(
+    conn: &Connection,
+    log_path: String,
+    date: String,
+    template: &LineTemplate,
+) -> error::Result<(ImportSummary, Vec<Error>)> {
+    let text = fs::read_to_string(log_path)?;
+    let mut parsed = parser::parse_log(&text, template);
+    for entry in parsed.entries.iter_mut() {
+        entry.checked = None;
+    }
+    let summary = log_entries_to_database(conn, &parsed.entries, &date, ImportMode::Replace)?;
+
+    Ok((summary, parsed.warnings))
 }
 
-fn process_task_line(line: String, one_log: &mut LogItem) -> Result<()> {
-    reset_time_fields(one_log).ok();
+/// Saves parsed entries that have a finished session and applies their checkboxes
+/// in a single transaction
+pub fn log_entries_to_database(
+    conn: &Connection,
+    entries: &[LogEntry],
+    date: &str,
+    mode: ImportMode,
+) -> error::Result<ImportSummary> {
+    let next_day = datetime::date_plus_n(date, 1)?;
+    let mut summary = ImportSummary::default();
+
+    in_transaction(conn, |tx| {
+        if mode == ImportMode::Replace {
+            delete_log_for_date(tx, date)?;
+        }
+        for entry in entries.iter() {
+            apply_checkbox(tx, entry, date, &next_day, &mut summary)?;
+            if !entry.is_logged() {
+                continue;
+            }
+            summary.saved += 1;
+            if mode == ImportMode::Merge && update_log_entry(tx, entry, date)? {
+                continue;
+            }
+            log_entry_to_database(tx, entry, date)?;
+        }
+        Ok(())
+    })?;
 
-    one_log.set_name(get_text_between(&line, "]", "：")?);
-    one_log.set_notes(get_text_after(&line, "：")?);
-    one_log.set_project(get_text_between(&line, "[", "]")?);
+    Ok(summary)
+}
 
-    let estimate_int = match get_text_between(&line, "(", ")").unwrap().parse::<i32>() {
-        Ok(estimate) => estimate,
-        Err(_) => panic!("Failed to read estimate for task {}", one_log.name),
+/// Checked one-off tasks become DONE, unchecked ones still planned for the date move to
+/// the next day once the date is over, and checked routines record a completion for the date
+/// Only one-off tasks dated on the date and routines that fall on it are matched, so a
+/// task with the same name on another day is left alone
+fn apply_checkbox(
+    conn: &Connection,
+    entry: &LogEntry,
+    date: &str,
+    next_day: &str,
+    summary: &mut ImportSummary,
+) -> Result<()> {
+    let checked = match entry.checked {
+        Some(checked) => checked,
+        None => return Ok(()),
+    };
+    let tasks = TaskQuery::new()
+        .name(&entry.name)
+        .project(&entry.project)
+        .status("ACTIVE")
+        .run(conn)?;
+    // A routine's next date may already be past the date when older logs are imported
+    let task = tasks.iter().find(|t| {
+        if t.repeat.is_empty() {
+            t.next == date
+        } else {
+            datetime::routine_falls_on(&t.next, &t.repeat, date)
+                || datetime::routine_falls_on(date, &t.repeat, &t.next)
+        }
+    });
+    let task = match task {
+        Some(task) => task,
+        None => return Ok(()),
     };
 
-    one_log.set_estimate(estimate_int);
+    if !task.repeat.is_empty() {
+        if checked && add_completion(conn, &task.id, date)? {
+            summary.routines_completed += 1;
+        }
+    } else if checked {
+        modify_status(conn, &task.id, "DONE")?;
+        summary.done += 1;
+    } else if date < datetime::yyyymmdd_today_plus_n(0).as_str() {
+        modify_date(conn, &task.id, next_day)?;
+        summary.rolled_over += 1;
+    }
 
     Ok(())
 }
 
-fn reset_time_fields(one_log: &mut LogItem) -> Result<()> {
-    one_log.set_start("".to_string());
-    one_log.set_end("".to_string());
-    Ok(())
+/// Records that a routine was done on given date
+/// Returns false if it was already recorded
+pub fn add_completion(conn: &Connection, task_id: &i32, date: &str) -> Result<bool> {
+    let mut stmt = conn.prepare("INSERT OR IGNORE INTO completion (id, date) VALUES (?, ?)")?;
+    let inserted = stmt.execute(params![task_id, date])?;
+
+    Ok(inserted > 0)
 }
 
-fn reset_review_fields(one_log: &mut LogItem) -> Result<()> {
-    one_log.set_review("".to_string());
+/// Inserts a log row spanning all sessions and one log_session row per session
+fn log_entry_to_database(conn: &Connection, entry: &LogEntry, date: &str) -> Result<()> {
+    let sessions = entry.complete_sessions();
+    let query = "INSERT INTO log (name, notes, project, date,
+        start, end, estimate, review) VALUES
+        (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)";
+    let param_slice = params![
+        entry.name,
+        entry.notes,
+        entry.project,
+        date,
+        sessions[0].start,
+        sessions[sessions.len() - 1].end,
+        entry.estimate,
+        entry.review
+    ];
+    execute_insert_query(conn, query, param_slice)?;
+
+    let log_id = conn.last_insert_rowid();
+    sessions_to_database(conn, log_id, &sessions)?;
+
     Ok(())
 }
 
-fn process_indented_line(line: String, one_log: &mut LogItem) -> Result<()> {
-    if get_text_after(&line, "  - ")?.trim().len() <= 5 {
-        let time = get_text_after(&line, "  - ")?;
-        if one_log.start == "" {
-            one_log.set_start(time);
-        } else {
-            one_log.set_end(time);
-        }
-    } else {
-        one_log.set_review(get_text_after(&line, "  - ")?);
+fn sessions_to_database(conn: &Connection, log_id: i64, sessions: &[Session]) -> Result<()> {
+    let query = "INSERT INTO log_session (log_id, start, end) VALUES (?1, ?2, ?3)";
+    for session in sessions {
+        execute_insert_query(conn, query, params![log_id, session.start, session.end])?;
     }
+
     Ok(())
 }
 
-fn get_text_between(s: &str, left: &str, right: &str) -> Result<String> {
-    let start_bytes = s.find(left).unwrap_or(0) + 1;
-    let end_bytes = s.find(right).unwrap_or(s.len());
-    let result = &s[start_bytes..end_bytes];
-    Ok(result.trim().to_string())
+/// Updates the stored row with the same date, name and start time and replaces its sessions
+/// Returns false when there is no such row
+fn update_log_entry(conn: &Connection, entry: &LogEntry, date: &str) -> Result<bool> {
+    let sessions = entry.complete_sessions();
+    let log_id: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM log WHERE date = ? and name = ? and start = ?",
+            params![date, entry.name, sessions[0].start],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    let log_id = match log_id {
+        Some(id) => id,
+        None => return Ok(false),
+    };
+
+    let mut stmt = conn.prepare(
+        "UPDATE log SET notes = ?, project = ?, end = ?, estimate = ?, review = ?
+         WHERE id = ?",
+    )?;
+    stmt.execute(params![
+        entry.notes,
+        entry.project,
+        sessions[sessions.len() - 1].end,
+        entry.estimate,
+        entry.review,
+        log_id
+    ])?;
+
+    let mut stmt = conn.prepare("DELETE FROM log_session WHERE log_id = ?")?;
+    stmt.execute(params![log_id])?;
+    sessions_to_database(conn, log_id, &sessions)?;
+
+    Ok(true)
 }
 
-fn get_text_after(s: &str, beginning: &str) -> Result<String> {
-    let v: Vec<&str> = s.split(beginning).collect();
-    Ok(v[1].trim().to_string())
+pub fn delete_log_for_date(conn: &Connection, date: &str) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "DELETE FROM log_session WHERE log_id IN (SELECT id FROM log WHERE date = ?)",
+    )?;
+    stmt.execute(params![date])?;
+    let mut stmt = conn.prepare("DELETE FROM log WHERE date = ?")?;
+    stmt.execute(params![date])?;
+
+    Ok(())
 }
 
-pub fn daily_report_log_vector(
-    conn: &Connection,
-    selection: Result<usize, std::io::Error>,
-    date_slice: &[String],
-) -> Result<Vec<Log>> {
-    let query = "SELECT id, name, notes, project, date, start, end, review
-                 FROM log WHERE date = ? ORDER BY start";
+pub fn daily_report_log_vector(conn: &Connection, date: &str) -> Result<Vec<Log>> {
+    let query = "SELECT l.id, l.name, l.notes, l.project, l.date, l.start, l.end, l.review,
+                 EXISTS (SELECT 1 FROM tasks as t
+                         WHERE t.name = l.name AND t.project = l.project),
+                 ifnull(l.estimate, 0)
+                 FROM log as l WHERE l.date = ? ORDER BY l.start";
+
+    let log_vector = query_to_vec_log(conn, query, params![date])?;
+    Ok(log_vector)
+}
 
-    let param = &date_slice[selection.unwrap()];
+/// Log rows from one date to another, ordered by date and start time
+pub fn range_log_vector(conn: &Connection, from: &str, to: &str) -> Result<Vec<Log>> {
+    let query = "SELECT l.id, l.name, l.notes, l.project, l.date, l.start, l.end, l.review,
+                 EXISTS (SELECT 1 FROM tasks as t
+                         WHERE t.name = l.name AND t.project = l.project),
+                 ifnull(l.estimate, 0)
+                 FROM log as l WHERE l.date >= ? AND l.date <= ? ORDER BY l.date, l.start";
 
-    let log_vector = query_to_vec_log(conn, &query, param)?;
+    let log_vector = query_to_vec_log(conn, query, params![from, to])?;
     Ok(log_vector)
 }
 
-fn query_to_vec_log(conn: &Connection, query: &str, param: &str) -> Result<Vec<Log>> {
-    let mut stmt = conn.prepare(&query)?;
+/// Log rows for given date that don't match any task by name and project
+pub fn unplanned_log_vector(conn: &Connection, date: &str) -> Result<Vec<Log>> {
+    let log_vector = daily_report_log_vector(conn, date)?;
+    Ok(log_vector.into_iter().filter(|l| !l.planned).collect())
+}
+
+fn query_to_vec_log(conn: &Connection, query: &str, param_slice: &[&dyn ToSql]) -> Result<Vec<Log>> {
+    let mut stmt = conn.prepare(query)?;
 
-    let log_iter = stmt.query_map(params![param], |row| {
+    let log_iter = stmt.query_map(param_slice, |row| {
         Ok(Log {
             id: row.get(0)?,
             name: row.get(1)?,
@@ -569,19 +968,51 @@ fn query_to_vec_log(conn: &Connection, query: &str, param: &str) -> Result<Vec<L
             date: row.get(4)?,
             start: row.get(5)?,
             end: row.get(6)?,
+            estimate: row.get(9)?,
             review: row.get(7)?,
+            sessions: Vec::new(),
+            planned: row.get(8)?,
         })
     })?;
 
     let mut vec = Vec::new();
     for log in log_iter {
-        let l = log.unwrap();
+        let mut l = log?;
+        l.sessions = get_log_sessions(conn, &l)?;
         vec.push(l);
     }
 
     Ok(vec)
 }
 
+/// Sessions of a log row in the order they were written in the markdown log
+/// Rows saved before sessions existed count as one session
+fn get_log_sessions(conn: &Connection, log: &Log) -> Result<Vec<Session>> {
+    let mut stmt = conn.prepare(
+        "SELECT start, end FROM log_session WHERE log_id = ? ORDER BY id",
+    )?;
+    let session_iter = stmt.query_map(params![log.id], |row| {
+        Ok(Session {
+            start: row.get(0)?,
+            end: row.get(1)?,
+        })
+    })?;
+
+    let mut sessions = Vec::new();
+    for session in session_iter {
+        sessions.push(session?);
+    }
+
+    if sessions.is_empty() {
+        sessions.push(Session {
+            start: log.start.clone(),
+            end: log.end.clone(),
+        });
+    }
+
+    Ok(sessions)
+}
+
 pub fn check_log_for_date(conn: &Connection, date: &str) -> Result<bool> {
     let count: i32 = conn.query_row("SELECT COUNT(*)
                                     FROM log
@@ -589,7 +1020,7 @@ pub fn check_log_for_date(conn: &Connection, date: &str) -> Result<bool> {
                                     &[&date],
                                     |row| row.get(0))?;
 
-    return if count > 0 { Ok(true) } else { Ok(false) }
+    Ok(count > 0)
 }
 
 pub fn repeat_next_updated(conn: &Connection) -> Result<bool> {
@@ -600,7 +1031,7 @@ pub fn repeat_next_updated(conn: &Connection) -> Result<bool> {
                                           &[&today],
                                           |row| row.get(0))?;
 
-    return if not_updated > 0 { Ok(false) } else { Ok(true) }
+    Ok(not_updated == 0)
 }
 
 pub fn update_routine_nexts(conn: &Connection) -> Result<()> {
@@ -612,3 +1043,258 @@ pub fn update_routine_nexts(conn: &Connection) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(key: &str, date: &str, start: &str, cancelled: bool) -> CalendarEvent {
+        CalendarEvent {
+            key: key.to_string(),
+            date: date.to_string(),
+            start: start.to_string(),
+            minutes: 30,
+            summary: format!("Meeting {}", key),
+            cancelled,
+        }
+    }
+
+    fn calendar_tasks(conn: &Connection) -> Vec<(String, String, String, String)> {
+        let mut tasks = TaskQuery::new()
+            .project(CALENDAR_PROJECT)
+            .run(conn)
+            .unwrap();
+        tasks.sort_by_key(|t| t.id);
+        tasks
+            .into_iter()
+            .map(|t| (t.name, t.status, t.next, t.start))
+            .collect()
+    }
+
+    fn import(conn: &Connection, events: &[CalendarEvent]) -> CalendarImportSummary {
+        calendar_events_to_database(conn, events, "2020-07-01", "2020-07-07").unwrap()
+    }
+
+    #[test]
+    fn reimport_updates_moved_events_by_key() {
+        let conn = Connection::open_in_memory().unwrap();
+        init(&conn).unwrap();
+
+        let summary = import(
+            &conn,
+            &[
+                event("a", "2020-07-01", "09:00", false),
+                event("b/2020-07-02", "2020-07-02", "10:00", false),
+            ],
+        );
+        assert_eq!((summary.added, summary.updated), (2, 0));
+
+        let summary = import(
+            &conn,
+            &[
+                event("a", "2020-07-03", "11:00", false),
+                event("b/2020-07-02", "2020-07-02", "10:00", false),
+            ],
+        );
+        assert_eq!((summary.added, summary.updated, summary.removed), (0, 2, 0));
+        assert_eq!(
+            calendar_tasks(&conn),
+            vec![
+                (
+                    "Meeting a".to_string(),
+                    "ACTIVE".to_string(),
+                    "2020-07-03".to_string(),
+                    "11:00".to_string()
+                ),
+                (
+                    "Meeting b/2020-07-02".to_string(),
+                    "ACTIVE".to_string(),
+                    "2020-07-02".to_string(),
+                    "10:00".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn deleted_task_is_added_again() {
+        let conn = Connection::open_in_memory().unwrap();
+        init(&conn).unwrap();
+
+        import(&conn, &[event("a", "2020-07-01", "09:00", false)]);
+        let id = TaskQuery::new()
+            .project(CALENDAR_PROJECT)
+            .run(&conn)
+            .unwrap()[0]
+            .id;
+        delete_task_by_id(&conn, &id).unwrap();
+
+        let summary = import(&conn, &[event("a", "2020-07-01", "09:00", false)]);
+        assert_eq!((summary.added, summary.updated), (1, 0));
+        assert_eq!(calendar_tasks(&conn).len(), 1);
+    }
+
+    #[test]
+    fn cancelled_and_missing_events_remove_active_tasks() {
+        let conn = Connection::open_in_memory().unwrap();
+        init(&conn).unwrap();
+
+        import(
+            &conn,
+            &[
+                event("a", "2020-07-01", "09:00", false),
+                event("b", "2020-07-02", "09:00", false),
+                event("c", "2020-07-03", "09:00", false),
+                event("d", "2020-07-04", "09:00", false),
+            ],
+        );
+        let done = TaskQuery::new().name("Meeting d").run(&conn).unwrap()[0].id;
+        modify_status(&conn, &done, "DONE").unwrap();
+
+        let summary = import(
+            &conn,
+            &[
+                event("a", "2020-07-01", "09:00", false),
+                event("b", "2020-07-02", "09:00", true),
+            ],
+        );
+        assert_eq!((summary.updated, summary.removed), (1, 2));
+        let names: Vec<String> = calendar_tasks(&conn).into_iter().map(|t| t.0).collect();
+        assert_eq!(names, vec!["Meeting a", "Meeting d"]);
+
+        // Rows outside the imported range are left alone
+        let summary = calendar_events_to_database(&conn, &[], "2020-07-08", "2020-07-14").unwrap();
+        assert_eq!(summary.removed, 0);
+        assert_eq!(calendar_tasks(&conn).len(), 2);
+    }
+
+    fn ticked_entries(tasks: &[Task]) -> Vec<LogEntry> {
+        let template = LineTemplate::default();
+        let text: String = tasks
+            .iter()
+            .map(|t| template.render_line(t).replacen("[ ]", "[x]", 1))
+            .collect();
+        parser::parse_log(&text, &template).entries
+    }
+
+    fn statuses(conn: &Connection) -> Vec<(String, String)> {
+        let mut tasks = TaskQuery::new().run(conn).unwrap();
+        tasks.sort_by_key(|t| t.id);
+        tasks.into_iter().map(|t| (t.next, t.status)).collect()
+    }
+
+    #[test]
+    fn checkbox_finishes_the_task_dated_on_the_log_date() {
+        let conn = Connection::open_in_memory().unwrap();
+        init(&conn).unwrap();
+        let email = Task::named("Email").at("09:00", 30).dated("2020-07-02");
+        add_task(&conn, email.clone()).unwrap();
+        add_task(&conn, email.clone().at("08:00", 30).dated("2020-07-10")).unwrap();
+
+        let entries = ticked_entries(&[email]);
+        let summary =
+            log_entries_to_database(&conn, &entries, "2020-07-02", ImportMode::Merge).unwrap();
+
+        assert_eq!(summary.done, 1);
+        assert_eq!(
+            statuses(&conn),
+            vec![
+                ("2020-07-02".to_string(), "DONE".to_string()),
+                ("2020-07-10".to_string(), "ACTIVE".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn checkbox_records_routines_only_on_their_days() {
+        let conn = Connection::open_in_memory().unwrap();
+        init(&conn).unwrap();
+        let weekly = Task::named("Review").repeating("+1w").dated("2020-07-01");
+        add_task(&conn, weekly.clone()).unwrap();
+        let entries = ticked_entries(&[weekly]);
+
+        for date in &["2020-06-24", "2020-07-03", "2020-07-08"] {
+            log_entries_to_database(&conn, &entries, date, ImportMode::Merge).unwrap();
+        }
+
+        let dates: Vec<String> = conn
+            .prepare("SELECT date FROM completion ORDER BY date")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| row.get(0))
+            .unwrap()
+            .map(|date| date.unwrap())
+            .collect();
+        assert_eq!(dates, vec!["2020-06-24", "2020-07-08"]);
+        assert_eq!(
+            statuses(&conn),
+            vec![("2020-07-01".to_string(), "ACTIVE".to_string())]
+        );
+    }
+
+    #[test]
+    fn calendar_range_starts_routines_in_the_range() {
+        let conn = Connection::open_in_memory().unwrap();
+        init(&conn).unwrap();
+        for task in [
+            Task::named("Weekly").repeating("+1w").dated("2020-06-05"),
+            Task::named("Fortnightly")
+                .repeating("+2w")
+                .dated("2020-06-13"),
+            Task::named("Monthly").repeating("+1m").dated("2020-06-15"),
+            Task::named("Later").repeating("+1d").dated("2020-07-20"),
+            Task::named("Once").dated("2020-07-02"),
+        ] {
+            add_task(&conn, task).unwrap();
+        }
+
+        let mut tasks: Vec<(String, String)> =
+            filter_by_calendar_range(&conn, "2020-07-01", "2020-07-07")
+                .unwrap()
+                .into_iter()
+                .map(|t| (t.name, t.next))
+                .collect();
+        tasks.sort();
+
+        assert_eq!(
+            tasks,
+            vec![
+                ("Once".to_string(), "2020-07-02".to_string()),
+                ("Weekly".to_string(), "2020-07-03".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn calendar_tasks_are_never_overdue() {
+        let conn = Connection::open_in_memory().unwrap();
+        init(&conn).unwrap();
+        import(&conn, &[event("a", "2020-07-01", "09:00", false)]);
+        add_task(&conn, Task::named("Report").dated("2020-07-01")).unwrap();
+
+        let overdue = filter_by_overdue(&conn, "2020-07-05").unwrap();
+
+        let names: Vec<&str> = overdue.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["Report"]);
+    }
+
+    #[test]
+    fn calendar_tasks_are_never_moved_off_an_overloaded_day() {
+        let conn = Connection::open_in_memory().unwrap();
+        init(&conn).unwrap();
+        // Added after the report, so without the filter it would be offered first
+        add_task(
+            &conn,
+            Task::named("Report").at("10:00", 30).dated("2020-07-01"),
+        )
+        .unwrap();
+        import(&conn, &[event("a", "2020-07-01", "09:00", false)]);
+
+        let moves = overload_moves(&conn, "2020-07-01", 40).unwrap();
+
+        let moved: Vec<(&str, &str)> = moves
+            .iter()
+            .map(|(t, day)| (t.name.as_str(), day.as_str()))
+            .collect();
+        assert_eq!(moved, vec![("Report", "2020-07-02")]);
+    }
+}